@@ -3,7 +3,7 @@ use std::path::Path;
 
 fn main() {
     // Initialize the embedded ShorterDB
-    let mut db = ShorterDB::new(Path::new("./embedded_db")).expect("Failed to initialize database");
+    let db = ShorterDB::new(Path::new("./embedded_db")).expect("Failed to initialize database");
 
     // Store "hello" and "world" in the database
     db.set(b"hello", b"world")
@@ -2,7 +2,6 @@ use proto::basic_server::{Basic, BasicServer};
 use proto::{GetRequest, GetResponse, SetRequest, SetResponse};
 use std::path::Path;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 use tonic::transport::Server;
 
 use shorterdb::ShorterDB;
@@ -12,7 +11,7 @@ mod proto {
 }
 
 struct DbOperations {
-    db: Arc<Mutex<ShorterDB>>,
+    db: Arc<ShorterDB>,
 }
 
 #[tonic::async_trait]
@@ -23,8 +22,7 @@ impl Basic for DbOperations {
     ) -> Result<tonic::Response<GetResponse>, tonic::Status> {
         let key = request.get_ref().key.clone();
 
-        let db = self.db.lock().await;
-        match db.get(key.as_bytes()) {
+        match self.db.get(key.as_bytes()) {
             Ok(Some(value)) => match std::str::from_utf8(&value) {
                 Ok(string_value) => {
                     let response = GetResponse {
@@ -46,8 +44,7 @@ impl Basic for DbOperations {
         let key = request.get_ref().key.clone();
         let value = request.get_ref().value.clone();
 
-        let mut db = self.db.lock().await;
-        match db.set(key.as_bytes(), value.as_bytes()) {
+        match self.db.set(key.as_bytes(), value.as_bytes()) {
             Ok(_) => {
                 let response = SetResponse { success: true };
                 Ok(tonic::Response::new(response))
@@ -61,7 +58,7 @@ impl Basic for DbOperations {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let addr = "[::1]:50051".parse()?;
 
-    let db = Arc::new(Mutex::new(ShorterDB::new(Path::new("./grpc_db"))?));
+    let db = Arc::new(ShorterDB::new(Path::new("./grpc_db"))?);
 
     let db_operations = DbOperations { db };
 
@@ -6,7 +6,7 @@ use std::path::Path;
 fn main() {
     let csv_file_path = "data.csv";
 
-    let mut db = ShorterDB::new(Path::new("./db_test")).expect("Failed to initialize database");
+    let db = ShorterDB::new(Path::new("./db_test")).expect("Failed to initialize database");
 
     let mut rdr = ReaderBuilder::new()
         .has_headers(false)
@@ -1,4 +1,6 @@
 // use serde_json;
+use crate::kv::options::CompactionStrategy;
+use crate::kv::stall::StallReason;
 use std::io;
 use thiserror::Error;
 
@@ -23,6 +25,81 @@ pub enum ShortDBErrors {
     ValueNotSet,
     #[error("We need to flush to sst, Max size for Memtable reached")]
     FlushNeededFromMemTable,
+    /// An SST file failed to decode.
+    #[error("Corrupted SST: {0}")]
+    CorruptedSST(String),
+    /// An SST file was written with a format version this build cannot read.
+    #[error("Unsupported SST format version {0}")]
+    UnsupportedSSTVersion(u32),
+    /// The database was opened with a different compaction strategy than it was created with.
+    #[error("Database uses {on_disk} compaction but {requested} was requested")]
+    CompactionStrategyMismatch {
+        on_disk: CompactionStrategy,
+        requested: CompactionStrategy,
+    },
+    /// An SST block was compressed with a codec this build does not include.
+    #[error("Unsupported compression codec {0}; is its cargo feature enabled, or its compressor registered?")]
+    UnsupportedCompression(u8),
+    /// The MANIFEST or CURRENT file could not be replayed.
+    #[error("Corrupted MANIFEST: {0}")]
+    CorruptedManifest(String),
+    /// The options given to open a database contradict each other.
+    #[error("Invalid options: {0}")]
+    InvalidOptions(String),
+    /// The OPTIONS file of the database could not be parsed, or lacks a
+    /// line it needs.
+    #[error("Invalid OPTIONS file line: {0}")]
+    InvalidOptionsFile(String),
+    /// The WAL ends in a record that was only partly written, as a crash
+    /// mid-append leaves it. Replay drops such a record.
+    #[error("Torn WAL record at offset {offset}")]
+    TornWALRecord { offset: u64 },
+    /// A complete WAL record failed its checksum or could not be decoded.
+    #[error("Corrupted WAL record at offset {offset}: {reason}")]
+    CorruptedWAL { offset: u64, reason: String },
+    /// A transaction touched a key that another write changed after the
+    /// transaction started, so it could not commit.
+    #[error("Transaction conflict: a key it used was changed by another write")]
+    Conflict,
+    /// A pessimistic transaction gave up waiting for a key locked by another.
+    #[error("Timed out waiting for a transaction lock")]
+    LockTimeout,
+    /// Waiting for a lock would have left two or more transactions waiting
+    /// on each other forever.
+    #[error("Deadlock detected while waiting for a transaction lock")]
+    Deadlock,
+    /// `rollback_to_savepoint` was called without a savepoint to go back to.
+    #[error("No savepoint to roll back to")]
+    NoSavepoint,
+    /// A key has merge operands but the database has no merge operator.
+    #[error("Key has merge operands but no merge operator is configured")]
+    NoMergeOperator,
+    /// The merge operator could not fold a key's operands.
+    #[error("Merge operator {0} failed")]
+    MergeFailed(String),
+    /// No column family has this name.
+    #[error("Unknown column family {0}")]
+    UnknownColumnFamily(String),
+    /// A column family with this name already exists.
+    #[error("Column family {0} already exists")]
+    ColumnFamilyExists(String),
+    /// The database has this column family, but it was not listed when
+    /// opening the database.
+    #[error("Column family {0} exists but was not opened")]
+    UnopenedColumnFamily(String),
+    /// Column family names must be non-empty and fit on one line, and the
+    /// default family can be neither created nor dropped.
+    #[error("Invalid column family name {0:?}")]
+    InvalidColumnFamilyName(String),
+    /// A flush or compaction running in the background failed, or a failed
+    /// WAL append could not be undone. The database stops taking writes;
+    /// what was written so far is recovered on reopen.
+    #[error("Background work failed: {0}")]
+    BackgroundError(String),
+    /// A write waited `Options::write_stall_timeout` for flushes or
+    /// compactions to catch up, and gave up.
+    #[error("Write stalled: {0}")]
+    WriteStall(StallReason),
 }
 
 /// Result type for kvs.
@@ -0,0 +1,68 @@
+use super::wal::WALEntry;
+
+/// A group of writes applied together by [`crate::ShorterDB::write`].
+///
+/// The whole batch goes to the WAL as a single checksummed record, so after
+/// a crash either every write in it is recovered or none is. That holds
+/// across column families too.
+#[derive(Default)]
+pub struct WriteBatch {
+    /// Each write with the column family it goes to; `None` is the default
+    /// family.
+    pub(crate) entries: Vec<(Option<String>, WALEntry)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> &mut Self {
+        self.entries.push((None, WALEntry::put(key, value)));
+        self
+    }
+
+    /// Adds a merge operand for `key`, see [`crate::ShorterDB::merge`].
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) -> &mut Self {
+        self.entries.push((None, WALEntry::merge(key, operand)));
+        self
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> &mut Self {
+        self.entries.push((None, WALEntry::delete(key)));
+        self
+    }
+
+    /// Like [`WriteBatch::put`], in column family `cf`.
+    pub fn put_cf(&mut self, cf: &str, key: &[u8], value: &[u8]) -> &mut Self {
+        self.entries
+            .push((Some(cf.to_string()), WALEntry::put(key, value)));
+        self
+    }
+
+    /// Like [`WriteBatch::merge`], in column family `cf`.
+    pub fn merge_cf(&mut self, cf: &str, key: &[u8], operand: &[u8]) -> &mut Self {
+        self.entries
+            .push((Some(cf.to_string()), WALEntry::merge(key, operand)));
+        self
+    }
+
+    /// Like [`WriteBatch::delete`], in column family `cf`.
+    pub fn delete_cf(&mut self, cf: &str, key: &[u8]) -> &mut Self {
+        self.entries
+            .push((Some(cf.to_string()), WALEntry::delete(key)));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,274 @@
+use crate::errors::{Result, ShortDBErrors};
+use bytes::Bytes;
+use std::cmp::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Target size of a data block before the table builder cuts a new one.
+pub(crate) const BLOCK_SIZE: usize = 4096;
+/// Table format versions 1 and 2 store entries without a sequence number.
+pub(crate) const FIRST_SEQUENCED_VERSION: u32 = 3;
+/// Table format versions before 4 store entries without an expiry.
+pub(crate) const FIRST_EXPIRING_VERSION: u32 = 4;
+
+/// What an entry in a block stands for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ValueKind {
+    Put = 0,
+    Delete = 1,
+    /// A merge operand, folded onto the versions below it when read.
+    Merge = 2,
+}
+
+impl ValueKind {
+    pub(crate) fn from_u8(b: u8) -> Result<Self> {
+        match b {
+            0 => Ok(ValueKind::Put),
+            1 => Ok(ValueKind::Delete),
+            2 => Ok(ValueKind::Merge),
+            other => Err(ShortDBErrors::CorruptedSST(format!(
+                "unknown value kind {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Entry {
+    pub(crate) key: Bytes,
+    /// Sequence number of the write that produced this version.
+    pub(crate) seq: u64,
+    pub(crate) kind: ValueKind,
+    /// Unix time in milliseconds from which a put reads as absent.
+    pub(crate) expires_at: Option<u64>,
+    pub(crate) value: Bytes,
+}
+
+impl Entry {
+    pub(crate) fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    /// Orders versions by key, and newest first within a key.
+    pub(crate) fn internal_cmp(&self, other: &Entry) -> Ordering {
+        self.key
+            .cmp(&other.key)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Builds a block laid out as:
+///
+/// ```text
+/// [key_len u32][key][seq u64][expires_at u64][kind u8][value_len u32][value]   * n
+/// [entry offset u32]                                                          * n
+/// [n u32]
+/// ```
+///
+/// Entries must be added in ascending key order, and newest first (by
+/// descending sequence number) within a key. The offset array lets readers
+/// binary search and walk the block in either direction. `expires_at` is a
+/// unix time in milliseconds, 0 for entries that never expire. Blocks of
+/// tables older than format version 3 have no `seq` field and read as
+/// sequence 0, blocks older than version 4 have no `expires_at` field.
+pub(crate) struct BlockBuilder {
+    buf: Vec<u8>,
+    offsets: Vec<u32>,
+}
+
+impl BlockBuilder {
+    pub(crate) fn new() -> Self {
+        BlockBuilder {
+            buf: Vec::new(),
+            offsets: Vec::new(),
+        }
+    }
+
+    pub(crate) fn add(
+        &mut self,
+        key: &[u8],
+        seq: u64,
+        kind: ValueKind,
+        expires_at: Option<u64>,
+        value: &[u8],
+    ) {
+        self.offsets.push(self.buf.len() as u32);
+        self.buf
+            .extend_from_slice(&(key.len() as u32).to_le_bytes());
+        self.buf.extend_from_slice(key);
+        self.buf.extend_from_slice(&seq.to_le_bytes());
+        self.buf
+            .extend_from_slice(&expires_at.unwrap_or(0).to_le_bytes());
+        self.buf.push(kind as u8);
+        self.buf
+            .extend_from_slice(&(value.len() as u32).to_le_bytes());
+        self.buf.extend_from_slice(value);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    pub(crate) fn estimated_size(&self) -> usize {
+        self.buf.len() + self.offsets.len() * 4 + 4
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        for offset in &self.offsets {
+            self.buf.extend_from_slice(&offset.to_le_bytes());
+        }
+        self.buf
+            .extend_from_slice(&(self.offsets.len() as u32).to_le_bytes());
+        self.buf
+    }
+}
+
+/// A decoded, immutable block.
+pub(crate) struct Block {
+    data: Bytes,
+    offsets: Vec<u32>,
+    /// Format version of the table the block belongs to.
+    version: u32,
+}
+
+impl Block {
+    pub(crate) fn decode(data: Bytes, version: u32) -> Result<Self> {
+        if data.len() < 4 {
+            return Err(ShortDBErrors::CorruptedSST("block too short".to_string()));
+        }
+        let n = read_u32(&data, data.len() - 4) as usize;
+        let offsets_start = data
+            .len()
+            .checked_sub(4 + n * 4)
+            .ok_or_else(|| ShortDBErrors::CorruptedSST("bad block entry count".to_string()))?;
+        let offsets = (0..n)
+            .map(|i| read_u32(&data, offsets_start + i * 4))
+            .collect();
+        Ok(Block {
+            data: data.slice(..offsets_start),
+            offsets,
+            version,
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Bytes of memory held by the decoded block.
+    pub(crate) fn size(&self) -> usize {
+        self.data.len() + self.offsets.len() * 4
+    }
+
+    pub(crate) fn key_at(&self, i: usize) -> Result<&[u8]> {
+        let pos = self.offsets[i] as usize;
+        let key_len = self.checked_u32(pos)? as usize;
+        self.checked_slice(pos + 4, key_len)
+    }
+
+    pub(crate) fn entry(&self, i: usize) -> Result<Entry> {
+        let mut pos = self.offsets[i] as usize;
+        let key_len = self.checked_u32(pos)? as usize;
+        pos += 4;
+        self.checked_slice(pos, key_len)?;
+        let key = self.data.slice(pos..pos + key_len);
+        pos += key_len;
+        let mut seq = 0;
+        if self.version >= FIRST_SEQUENCED_VERSION {
+            seq = self.checked_u64(pos)?;
+            pos += 8;
+        }
+        let mut expires_at = None;
+        if self.version >= FIRST_EXPIRING_VERSION {
+            expires_at = Some(self.checked_u64(pos)?).filter(|&at| at != 0);
+            pos += 8;
+        }
+        let kind = ValueKind::from_u8(*self.checked_slice(pos, 1)?.first().unwrap())?;
+        pos += 1;
+        let value_len = self.checked_u32(pos)? as usize;
+        pos += 4;
+        self.checked_slice(pos, value_len)?;
+        let value = self.data.slice(pos..pos + value_len);
+        Ok(Entry {
+            key,
+            seq,
+            kind,
+            expires_at,
+            value,
+        })
+    }
+
+    pub(crate) fn seq_at(&self, i: usize) -> Result<u64> {
+        if self.version < FIRST_SEQUENCED_VERSION {
+            return Ok(0);
+        }
+        let pos = self.offsets[i] as usize;
+        let key_len = self.checked_u32(pos)? as usize;
+        self.checked_u64(pos + 4 + key_len)
+    }
+
+    /// Index of the first entry whose key is `>= key`, or `len()` if there is none.
+    pub(crate) fn seek(&self, key: &[u8]) -> Result<usize> {
+        self.partition_point(|block, i| Ok(block.key_at(i)? < key))
+    }
+
+    /// Index of the first entry whose key is `> key`, or `len()` if there is none.
+    pub(crate) fn seek_after(&self, key: &[u8]) -> Result<usize> {
+        self.partition_point(|block, i| Ok(block.key_at(i)? <= key))
+    }
+
+    /// Index of the newest version of `key` no newer than `seq`, or of the
+    /// first entry past it if there is no such version.
+    pub(crate) fn seek_version(&self, key: &[u8], seq: u64) -> Result<usize> {
+        self.partition_point(|block, i| {
+            Ok(match block.key_at(i)?.cmp(key) {
+                Ordering::Less => true,
+                Ordering::Equal => block.seq_at(i)? > seq,
+                Ordering::Greater => false,
+            })
+        })
+    }
+
+    /// Binary search for the first entry `before` is false for.
+    fn partition_point(&self, before: impl Fn(&Block, usize) -> Result<bool>) -> Result<usize> {
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if before(self, mid)? {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        Ok(lo)
+    }
+
+    fn checked_slice(&self, pos: usize, len: usize) -> Result<&[u8]> {
+        self.data
+            .get(pos..pos + len)
+            .ok_or_else(|| ShortDBErrors::CorruptedSST("block entry out of bounds".to_string()))
+    }
+
+    fn checked_u32(&self, pos: usize) -> Result<u32> {
+        let bytes = self.checked_slice(pos, 4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn checked_u64(&self, pos: usize) -> Result<u64> {
+        let bytes = self.checked_slice(pos, 8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+/// Milliseconds since the unix epoch, the unit entry expiries are kept in.
+/// Saturates at `u64::MAX`, and never returns 0, which marks an entry that
+/// does not expire: times at or before the epoch come out as 1.
+pub(crate) fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(1, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+        .max(1)
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
@@ -0,0 +1,153 @@
+//! Sharded LRU cache of decoded SST data blocks.
+//!
+//! A single [`BlockCache`] can be handed to several databases through
+//! [`crate::Options::block_cache`] to bound the memory they use together.
+//! Blocks are keyed by their table's file number and offset; since file
+//! numbers are only unique within one database, every database that opens
+//! the cache also gets its own id to keep their keys apart.
+
+use super::block::Block;
+use parking_lot::Mutex;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+const NUM_SHARDS: usize = 16;
+
+/// `(database id, file number, block offset)`
+type CacheKey = (u64, u64, u64);
+
+/// Counters reported by [`BlockCache::stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Bytes of blocks currently held.
+    pub usage: usize,
+    pub capacity: usize,
+}
+
+pub struct BlockCache {
+    shards: Vec<Mutex<Shard>>,
+    capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    next_id: AtomicU64,
+}
+
+impl BlockCache {
+    /// A cache holding up to `capacity` bytes of blocks, split evenly
+    /// between its shards.
+    pub fn new(capacity: usize) -> Self {
+        let per_shard = capacity.div_ceil(NUM_SHARDS);
+        BlockCache {
+            shards: (0..NUM_SHARDS)
+                .map(|_| Mutex::new(Shard::new(per_shard)))
+                .collect(),
+            capacity,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            usage: self.shards.iter().map(|s| s.lock().usage).sum(),
+            capacity: self.capacity,
+        }
+    }
+
+    /// A new id for a database sharing this cache.
+    pub(crate) fn register(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub(crate) fn get(&self, key: CacheKey) -> Option<Arc<Block>> {
+        let found = self.shard(&key).lock().get(&key);
+        let counter = if found.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    pub(crate) fn insert(&self, key: CacheKey, block: Arc<Block>) {
+        self.shard(&key).lock().insert(key, block);
+    }
+
+    fn shard(&self, key: &CacheKey) -> &Mutex<Shard> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % NUM_SHARDS]
+    }
+}
+
+impl fmt::Debug for BlockCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockCache")
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+struct Shard {
+    capacity: usize,
+    usage: usize,
+    /// Bumped on every access; the entry with the smallest tick is the least
+    /// recently used.
+    tick: u64,
+    entries: HashMap<CacheKey, (Arc<Block>, u64)>,
+    lru: BTreeMap<u64, CacheKey>,
+}
+
+impl Shard {
+    fn new(capacity: usize) -> Self {
+        Shard {
+            capacity,
+            usage: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &CacheKey) -> Option<Arc<Block>> {
+        self.tick += 1;
+        let (block, tick) = self.entries.get_mut(key)?;
+        self.lru.remove(tick);
+        *tick = self.tick;
+        self.lru.insert(self.tick, *key);
+        Some(Arc::clone(block))
+    }
+
+    fn insert(&mut self, key: CacheKey, block: Arc<Block>) {
+        let charge = block.size();
+        if charge > self.capacity {
+            return;
+        }
+        if let Some((old, tick)) = self.entries.remove(&key) {
+            self.lru.remove(&tick);
+            self.usage -= old.size();
+        }
+        while self.usage + charge > self.capacity {
+            let Some((_, oldest)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some((evicted, _)) = self.entries.remove(&oldest) {
+                self.usage -= evicted.size();
+            }
+        }
+        self.tick += 1;
+        self.usage += charge;
+        self.entries.insert(key, (block, self.tick));
+        self.lru.insert(self.tick, key);
+    }
+}
@@ -0,0 +1,420 @@
+//! Column families.
+//!
+//! A database holds one or more named keyspaces. Each has its own Memtable,
+//! SST levels, MANIFEST and options, but they all share the database's WAL
+//! and sequence numbers, so a [`crate::WriteBatch`] spanning several
+//! families is still logged as one record and recovered all or nothing.
+//!
+//! The `default` family lives at the top of the database directory, where a
+//! database without families has always kept its files. Every other family
+//! lives in `cf/<id>`, and the `COLUMN_FAMILIES` file names them:
+//!
+//! ```text
+//! next_id=3
+//! 1=users
+//! 2=orders
+//! ```
+//!
+//! Ids are never reused, so WAL records of a dropped family are recognised
+//! and skipped on replay.
+
+use super::{
+    block::{unix_millis, Entry},
+    flush::FlushJob,
+    iterator::{DBIterator, EntryIterator, KeyRange},
+    manifest::write_atomically,
+    memtable::Memtable,
+    merge::resolve,
+    options::Options,
+    snapshot::SnapshotList,
+    sst::SST,
+    stall::Stall,
+    wal::WALEntry,
+};
+use crate::errors::{Result, ShortDBErrors};
+use parking_lot::RwLock;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Name of the column family every database has, and which the methods
+/// without a `_cf` suffix use.
+pub const DEFAULT_COLUMN_FAMILY: &str = "default";
+pub(crate) const DEFAULT_ID: u32 = 0;
+const COLUMN_FAMILIES_FILE: &str = "COLUMN_FAMILIES";
+
+/// One keyspace: its Memtable and SST levels.
+pub(crate) struct ColumnFamily {
+    pub(crate) memtable: Memtable,
+    pub(crate) sst: SST,
+    /// Set once the family is dropped, so a flush still holding it leaves
+    /// its deleted directory alone.
+    pub(crate) dropped: bool,
+}
+
+impl ColumnFamily {
+    fn open(dir: &Path, options: Options, snapshots: SnapshotList) -> Result<Self> {
+        options.validate()?;
+        fs::create_dir_all(dir)?;
+        options.check_or_persist(dir)?;
+        Ok(ColumnFamily {
+            memtable: Memtable::new(),
+            sst: SST::open(dir, options, snapshots)?,
+            dropped: false,
+        })
+    }
+
+    /// Looks `key` up as of sequence number `seq`. A deleted or expired key
+    /// is reported as `Ok(None)` and a key that was never written as
+    /// `KeyNotFound`.
+    pub(crate) fn get_at(&self, key: &[u8], seq: u64) -> Result<Option<Vec<u8>>> {
+        // Walk down the versions of the key, newest first, as far as merge
+        // operands need a base to fold onto.
+        let mut next = Some(seq);
+        let mut versions = std::iter::from_fn(|| {
+            let version = self.entry_at(key, next?).transpose()?;
+            next = match &version {
+                Ok(entry) => entry.seq.checked_sub(1),
+                Err(_) => None,
+            };
+            Some(version)
+        })
+        .peekable();
+        if versions.peek().is_none() {
+            return Err(ShortDBErrors::KeyNotFound);
+        }
+        resolve(
+            self.sst.options.merge_operator.as_deref(),
+            key,
+            versions,
+            unix_millis(SystemTime::now()),
+        )
+    }
+
+    /// The newest version of `key` no newer than `seq`, tombstones included.
+    pub(crate) fn entry_at(&self, key: &[u8], seq: u64) -> Result<Option<Entry>> {
+        // First check in Memtable, then in the queued ones and SST
+        match self.memtable.get_entry(key, seq) {
+            Some(entry) => Ok(Some(entry)),
+            None => self.sst.get_entry(key, seq),
+        }
+    }
+
+    /// Scans `range` as of sequence number `seq`.
+    pub(crate) fn scan_at(&self, range: KeyRange, seq: u64) -> DBIterator {
+        let mut sources: Vec<EntryIterator> =
+            vec![Box::new(self.memtable.entries(&range).into_iter().map(Ok))];
+        sources.extend(self.sst.sources(&range));
+        DBIterator::new(sources, seq, self.sst.options.merge_operator.clone())
+    }
+
+    /// Queues the Memtable for flushing and starts a fresh one, unless it is
+    /// empty. Every write logged up to `last_sequence` is then either in the
+    /// queue or this family's tables, or belongs to another family.
+    fn freeze(&mut self, last_sequence: u64) -> Option<Arc<Memtable>> {
+        if self.memtable.memtable.is_empty() {
+            return None;
+        }
+        let memtable = Arc::new(std::mem::replace(&mut self.memtable, Memtable::new()));
+        self.sst
+            .queue
+            .push_back((last_sequence, Arc::clone(&memtable)));
+        Some(memtable)
+    }
+
+    /// Writes the Memtable out to SST right away, on the calling thread.
+    fn flush(&mut self, last_sequence: u64) -> Result<()> {
+        if let Some(memtable) = self.freeze(last_sequence) {
+            let table = self.sst.prepare_flush().write(&memtable)?;
+            self.sst.install_flush(table, last_sequence)?;
+        }
+        self.sst.compact()
+    }
+}
+
+/// Every column family of a database, by id.
+///
+/// Each family sits behind its own lock. Reads and Memtable inserts share
+/// it, since the Memtable takes concurrent inserts; swapping the Memtable
+/// or changing the SST levels takes it exclusively, but only for as long as
+/// that takes: the flush thread writes tables without holding it.
+pub(crate) struct ColumnFamilies {
+    dir: PathBuf,
+    next_id: u32,
+    /// Ids of the families other than `default`, by name.
+    ids: BTreeMap<String, u32>,
+    families: HashMap<u32, Arc<RwLock<ColumnFamily>>>,
+    /// Shared by every family: a snapshot sees all of them as of one write.
+    pub(crate) snapshots: SnapshotList,
+}
+
+impl ColumnFamilies {
+    /// Opens the default family with `options` and every family in
+    /// `listed` with its own options, creating those that do not exist yet.
+    /// Fails with `UnopenedColumnFamily` if the database has a family that
+    /// is not listed.
+    pub(crate) fn open(dir: &Path, options: Options, listed: Vec<(&str, Options)>) -> Result<Self> {
+        let mut families = ColumnFamilies {
+            dir: dir.to_path_buf(),
+            next_id: DEFAULT_ID + 1,
+            ids: BTreeMap::new(),
+            families: HashMap::new(),
+            snapshots: SnapshotList::default(),
+        };
+        families.load()?;
+        for name in families.ids.keys() {
+            if !listed.iter().any(|(listed, _)| listed == name) {
+                return Err(ShortDBErrors::UnopenedColumnFamily(name.clone()));
+            }
+        }
+
+        let default = ColumnFamily::open(dir, options, families.snapshots.clone())?;
+        families
+            .families
+            .insert(DEFAULT_ID, Arc::new(RwLock::new(default)));
+        for (name, options) in listed {
+            match families.ids.get(name) {
+                Some(&id) => {
+                    let family = ColumnFamily::open(
+                        &families.family_dir(id),
+                        options,
+                        families.snapshots.clone(),
+                    )?;
+                    families.families.insert(id, Arc::new(RwLock::new(family)));
+                }
+                None => families.create(name, options)?,
+            }
+        }
+        families.remove_dropped()?;
+        Ok(families)
+    }
+
+    /// Reads `COLUMN_FAMILIES`, if the database has one.
+    fn load(&mut self) -> Result<()> {
+        let contents = match fs::read_to_string(self.dir.join(COLUMN_FAMILIES_FILE)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        for line in contents.lines() {
+            let bad_line = || {
+                ShortDBErrors::CorruptedManifest(format!(
+                    "bad {} line: {:?}",
+                    COLUMN_FAMILIES_FILE, line
+                ))
+            };
+            let (id, name) = line.split_once('=').ok_or_else(bad_line)?;
+            if id == "next_id" {
+                self.next_id = name.parse().map_err(|_| bad_line())?;
+            } else {
+                let id = id.parse().map_err(|_| bad_line())?;
+                self.ids.insert(name.to_string(), id);
+            }
+        }
+        Ok(())
+    }
+
+    fn persist(&self) -> Result<()> {
+        let mut contents = format!("next_id={}\n", self.next_id);
+        for (name, id) in &self.ids {
+            contents.push_str(&format!("{}={}\n", id, name));
+        }
+        write_atomically(&self.dir, COLUMN_FAMILIES_FILE, contents.as_bytes())?;
+        Ok(())
+    }
+
+    fn family_dir(&self, id: u32) -> PathBuf {
+        self.dir.join("cf").join(id.to_string())
+    }
+
+    /// Clears out directories of families that were dropped, or whose
+    /// creation never got recorded.
+    fn remove_dropped(&self) -> Result<()> {
+        let cf_dir = self.dir.join("cf");
+        if !cf_dir.is_dir() {
+            return Ok(());
+        }
+        for child in cf_dir.read_dir()? {
+            let path = child?.path();
+            let id = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.parse::<u32>().ok());
+            if !id.is_some_and(|id| self.families.contains_key(&id)) {
+                fs::remove_dir_all(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Id of the family called `name`.
+    pub(crate) fn id(&self, name: &str) -> Result<u32> {
+        if name == DEFAULT_COLUMN_FAMILY {
+            return Ok(DEFAULT_ID);
+        }
+        self.ids
+            .get(name)
+            .copied()
+            .ok_or_else(|| ShortDBErrors::UnknownColumnFamily(name.to_string()))
+    }
+
+    pub(crate) fn default_family(&self) -> &Arc<RwLock<ColumnFamily>> {
+        &self.families[&DEFAULT_ID]
+    }
+
+    pub(crate) fn named(&self, name: &str) -> Result<&Arc<RwLock<ColumnFamily>>> {
+        Ok(&self.families[&self.id(name)?])
+    }
+
+    /// Fails with `NoMergeOperator` unless family `id` can fold merge
+    /// operands.
+    pub(crate) fn check_merge_operator(&self, id: u32) -> Result<()> {
+        let family = self.families[&id].read();
+        match family.sst.options.merge_operator {
+            Some(_) => Ok(()),
+            None => Err(ShortDBErrors::NoMergeOperator),
+        }
+    }
+
+    pub(crate) fn all(&self) -> impl Iterator<Item = &Arc<RwLock<ColumnFamily>>> {
+        self.families.values()
+    }
+
+    /// Names of every family, `default` first.
+    pub(crate) fn names(&self) -> Vec<String> {
+        let mut names = vec![DEFAULT_COLUMN_FAMILY.to_string()];
+        names.extend(self.ids.keys().cloned());
+        names
+    }
+
+    pub(crate) fn create(&mut self, name: &str, options: Options) -> Result<()> {
+        if name == DEFAULT_COLUMN_FAMILY || name.is_empty() || name.contains(['\n', '\r']) {
+            return Err(ShortDBErrors::InvalidColumnFamilyName(name.to_string()));
+        }
+        if self.ids.contains_key(name) {
+            return Err(ShortDBErrors::ColumnFamilyExists(name.to_string()));
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.ids.insert(name.to_string(), id);
+        // Record the family before creating it; a directory no record
+        // points to is removed on the next open.
+        self.persist()?;
+        let family = ColumnFamily::open(&self.family_dir(id), options, self.snapshots.clone())?;
+        self.families.insert(id, Arc::new(RwLock::new(family)));
+        Ok(())
+    }
+
+    /// Drops the family called `name` along with all of its data.
+    pub(crate) fn remove(&mut self, name: &str) -> Result<()> {
+        if name == DEFAULT_COLUMN_FAMILY {
+            return Err(ShortDBErrors::InvalidColumnFamilyName(name.to_string()));
+        }
+        let id = self.id(name)?;
+        self.ids.remove(name);
+        self.persist()?;
+        if let Some(family) = self.families.remove(&id) {
+            family.write().dropped = true;
+        }
+        fs::remove_dir_all(self.family_dir(id))?;
+        Ok(())
+    }
+
+    /// Inserts a logged write into the Memtable of its family and returns
+    /// whether that Memtable is now full. Writes of dropped families, and
+    /// writes replayed from the WAL that a flush already covers, are
+    /// skipped.
+    pub(crate) fn insert(&self, seq: u64, entry: &WALEntry) -> Result<bool> {
+        let family = match self.families.get(&entry.cf) {
+            Some(family) => family.read(),
+            None => return Ok(false),
+        };
+        if seq <= family.sst.last_sequence {
+            return Ok(false);
+        }
+        match family.memtable.add(seq, entry) {
+            Err(ShortDBErrors::FlushNeededFromMemTable) => Ok(true),
+            other => other.map(|_| false),
+        }
+    }
+
+    /// Flushes the Memtable of family `id` on the calling thread.
+    pub(crate) fn flush(&self, id: u32, last_sequence: u64) -> Result<()> {
+        match self.families.get(&id) {
+            Some(family) => family.write().flush(last_sequence),
+            None => Ok(()),
+        }
+    }
+
+    /// Newest write any family's tables cover.
+    pub(crate) fn last_sequence(&self) -> u64 {
+        self.families
+            .values()
+            .map(|f| f.read().sst.last_sequence)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Freezes the Memtable of every family that has writes, so that all of
+    /// them up to `last_sequence` can leave the WAL once the returned jobs
+    /// are done.
+    pub(crate) fn freeze(&self, last_sequence: u64) -> Vec<FlushJob> {
+        self.families
+            .values()
+            .filter_map(|family| {
+                let memtable = family.write().freeze(last_sequence)?;
+                Some(FlushJob {
+                    family: Arc::clone(family),
+                    memtable,
+                    last_sequence,
+                    release: None,
+                })
+            })
+            .collect()
+    }
+
+    /// How much the family furthest behind holds writes back, if at all.
+    pub(crate) fn stall(&self) -> Option<Stall> {
+        let mut worst = None;
+        for family in self.families.values() {
+            match family.read().stall() {
+                Some(stop @ Stall::Stop(_)) => return Some(stop),
+                Some(delay) => worst = worst.or(Some(delay)),
+                None => {}
+            }
+        }
+        worst
+    }
+
+    /// Every write that lives in a Memtable alone, in the order it was
+    /// logged: what the WAL still has to hold.
+    pub(crate) fn pending_writes(&self) -> Vec<(u64, WALEntry)> {
+        let mut pending: Vec<(u64, WALEntry)> = self
+            .families
+            .iter()
+            .flat_map(|(&cf, family)| {
+                family
+                    .read()
+                    .memtable
+                    .entries(&(Bound::Unbounded, Bound::Unbounded))
+                    .into_iter()
+                    .map(move |e| {
+                        let entry = WALEntry {
+                            cf,
+                            kind: e.kind,
+                            key: e.key,
+                            value: e.value,
+                            expires_at: e.expires_at,
+                        };
+                        (e.seq, entry)
+                    })
+            })
+            .collect();
+        pending.sort_by_key(|(seq, _)| *seq);
+        pending
+    }
+}
@@ -0,0 +1,160 @@
+//! Group commit.
+//!
+//! Writers queue their writes here before they touch the WAL. The first
+//! one to find no leader leads: it takes the WAL lock and every write
+//! queued by then, its own included, logs them with one `write_all` and at
+//! most one sync, then applies them and publishes them together. Every
+//! writer it took along waits on its own slot, and the leader wakes each
+//! of them with the result of the group. If more writes queued up
+//! meanwhile, the leader hands the lead to the oldest of them before it
+//! returns, so the next group holds everyone who arrived while the last
+//! one was being logged and a sync is shared by all of them.
+
+use super::wal::WALEntry;
+use crate::errors::{Result, ShortDBErrors};
+use parking_lot::{Condvar, Mutex};
+use std::io;
+use std::sync::Arc;
+
+/// Counters reported by [`crate::ShorterDB::write_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteStats {
+    /// Writes logged, each as one WAL record.
+    pub writes: u64,
+    /// Appends to the WAL; a group of writes takes one.
+    pub appends: u64,
+    /// Syncs of the WAL to disk, at most one per append.
+    pub syncs: u64,
+}
+
+enum SlotState {
+    Waiting,
+    /// The writer is to lead the next group.
+    Leading,
+    Done(Result<()>),
+}
+
+/// Where a writer waits for its turn to lead or for the result of its
+/// write.
+struct Slot {
+    state: Mutex<SlotState>,
+    ready: Condvar,
+}
+
+impl Slot {
+    fn new(state: SlotState) -> Arc<Self> {
+        Arc::new(Slot {
+            state: Mutex::new(state),
+            ready: Condvar::new(),
+        })
+    }
+
+    fn set(&self, state: SlotState) {
+        *self.state.lock() = state;
+        self.ready.notify_one();
+    }
+}
+
+/// A write waiting to be logged, and where its writer waits for the result.
+pub(crate) struct PendingWrite {
+    pub(crate) entries: Vec<WALEntry>,
+    slot: Arc<Slot>,
+}
+
+impl PendingWrite {
+    /// Hands the result of the group to this write's writer and wakes it.
+    pub(crate) fn finish(&self, result: &Result<()>) {
+        let result = match result {
+            Ok(()) => Ok(()),
+            Err(e) => Err(share(e)),
+        };
+        self.slot.set(SlotState::Done(result));
+    }
+}
+
+/// A writer's claim on the result of its queued write.
+pub(crate) struct Ticket(Arc<Slot>);
+
+impl Ticket {
+    /// Blocks until the write is logged and returns its result, or until
+    /// the writer is to lead the next group, and then returns `None`.
+    pub(crate) fn wait(&self) -> Option<Result<()>> {
+        let mut state = self.0.state.lock();
+        loop {
+            match std::mem::replace(&mut *state, SlotState::Waiting) {
+                SlotState::Waiting => self.0.ready.wait(&mut state),
+                SlotState::Leading => return None,
+                SlotState::Done(result) => return Some(result),
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    pending: Vec<PendingWrite>,
+    /// Whether some writer leads the writes queued so far.
+    leading: bool,
+}
+
+#[derive(Default)]
+pub(crate) struct CommitQueue {
+    state: Mutex<QueueState>,
+    stats: Mutex<WriteStats>,
+}
+
+impl CommitQueue {
+    /// Queues `entries`. The writer leads the next group if nobody else
+    /// does.
+    pub(crate) fn push(&self, entries: Vec<WALEntry>) -> Ticket {
+        let mut state = self.state.lock();
+        let slot = if state.leading {
+            Slot::new(SlotState::Waiting)
+        } else {
+            state.leading = true;
+            Slot::new(SlotState::Leading)
+        };
+        state.pending.push(PendingWrite {
+            entries,
+            slot: Arc::clone(&slot),
+        });
+        Ticket(slot)
+    }
+
+    /// Takes every queued write, oldest first, to log as one group.
+    pub(crate) fn take(&self) -> Vec<PendingWrite> {
+        std::mem::take(&mut self.state.lock().pending)
+    }
+
+    /// Called by a leader once its group is logged: the oldest write queued
+    /// since leads the next group.
+    pub(crate) fn hand_off(&self) {
+        let mut state = self.state.lock();
+        match state.pending.first() {
+            Some(next) => next.slot.set(SlotState::Leading),
+            None => state.leading = false,
+        }
+    }
+
+    /// Accounts for one append to the WAL holding `writes` records.
+    pub(crate) fn record(&self, writes: usize, synced: bool) {
+        let mut stats = self.stats.lock();
+        stats.writes += writes as u64;
+        stats.appends += 1;
+        stats.syncs += synced as u64;
+    }
+
+    pub(crate) fn stats(&self) -> WriteStats {
+        *self.stats.lock()
+    }
+}
+
+/// Gives every writer of a failed group its own copy of the error.
+fn share(e: &ShortDBErrors) -> ShortDBErrors {
+    match e {
+        ShortDBErrors::Io(e) => ShortDBErrors::Io(io::Error::new(e.kind(), e.to_string())),
+        ShortDBErrors::BackgroundError(e) => ShortDBErrors::BackgroundError(e.clone()),
+        ShortDBErrors::WriteStall(reason) => ShortDBErrors::WriteStall(*reason),
+        e => ShortDBErrors::Io(io::Error::other(e.to_string())),
+    }
+}
@@ -0,0 +1,551 @@
+//! Leveled and universal compaction.
+//!
+//! Leveled: level 0 holds whole memtable flushes and may overlap; every
+//! deeper level is a sorted run of non-overlapping tables, ten times bigger
+//! than its parent. When a level goes over budget, tables from it are merged
+//! with the overlapping tables of the next level and the result replaces both.
+//!
+//! Universal: every table stays in level 0 as its own sorted run, newest
+//! first. Runs are only merged once enough of them have piled up, picking
+//! the newest runs of similar size so that data is rewritten far less often.
+//!
+//! Either way, a merge keeps the newest version of every key plus, for each
+//! live snapshot, the newest version that snapshot can see, folds merge
+//! operands onto the values below them and purges the values of expired
+//! keys. The versions of
+//! one key are never split across two output tables.
+
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use bytes::Bytes;
+
+use super::{
+    block::{unix_millis, Entry, ValueKind},
+    iterator::{EntryIterator, MergingIterator},
+    manifest::{FileMeta, VersionEdit},
+    merge::{full_merge, MergeOperator},
+    options::{CompactionStrategy, CompressionType},
+    sst::{sort_level0, table_file_name, SST},
+    table::{Table, TableBuilder, TableOptions},
+};
+use crate::errors::Result;
+
+/// Number of level 0 tables that triggers a compaction into level 1.
+pub(crate) const L0_COMPACTION_TRIGGER: usize = 4;
+/// Deepest level a table can end up in.
+pub(crate) const MAX_LEVELS: usize = 7;
+/// Compaction output is split into tables of roughly this size.
+pub(crate) const TARGET_FILE_SIZE: u64 = 256 * 1024;
+/// Universal: a run joins the merge if it is at most this many percent
+/// bigger than all the newer runs picked so far combined.
+const UNIVERSAL_SIZE_RATIO: u64 = 1;
+/// Universal: merge everything once the newer runs add up to this many
+/// percent of the oldest run.
+const UNIVERSAL_MAX_SIZE_AMPLIFICATION: u64 = 200;
+/// Universal: fewest runs worth merging by size ratio.
+const UNIVERSAL_MIN_MERGE_WIDTH: usize = 2;
+
+/// One unit of compaction work, with everything needed to run it without
+/// holding the SST.
+pub(crate) struct Compaction {
+    pub(crate) level: usize,
+    pub(crate) output_level: usize,
+    /// Tables taken from `level`; newest first when `level` is 0.
+    pub(crate) inputs: Vec<Arc<Table>>,
+    /// Tables of `output_level` overlapping the inputs, in key order.
+    pub(crate) overlapping: Vec<Arc<Table>>,
+    /// Every other table at or below the output level: a key none of them
+    /// can hold has nothing older left.
+    below: Vec<Arc<Table>>,
+    output_dir: PathBuf,
+    file_numbers: Arc<AtomicU64>,
+    compression: CompressionType,
+    table_options: TableOptions,
+    retention: Retention,
+}
+
+impl Compaction {
+    /// The manifest edit swapping the inputs for `outputs`.
+    fn edit(&self, outputs: &[Arc<Table>]) -> VersionEdit {
+        VersionEdit {
+            added: outputs
+                .iter()
+                .map(|t| FileMeta::of(t, self.output_level))
+                .collect(),
+            deleted: self
+                .inputs
+                .iter()
+                .map(|t| (self.level, t.file_number))
+                .chain(
+                    self.overlapping
+                        .iter()
+                        .map(|t| (self.output_level, t.file_number)),
+                )
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn tables(&self) -> impl Iterator<Item = &Arc<Table>> {
+        self.inputs.iter().chain(&self.overlapping)
+    }
+
+    fn is_input(&self, table: &Table) -> bool {
+        self.tables().any(|t| t.file_number == table.file_number)
+    }
+
+    /// A lone table with nothing below it to merge with just changes level.
+    fn is_trivial_move(&self) -> bool {
+        self.level > 0 && self.inputs.len() == 1 && self.overlapping.is_empty()
+    }
+
+    /// Merges the inputs into new tables and returns them, or `None` if
+    /// `canceled` turned true first. Nothing of the output is left behind
+    /// unless it succeeds.
+    pub(crate) fn run(&self, canceled: &dyn Fn() -> bool) -> Result<Option<Vec<Arc<Table>>>> {
+        if self.is_trivial_move() {
+            let table = &self.inputs[0];
+            let path = self.output_dir.join(table_file_name(table.file_number));
+            fs::rename(&table.path, &path)?;
+            let moved = Table::open(&path, table.file_number, &self.table_options)?;
+            return Ok(Some(vec![Arc::new(moved)]));
+        }
+
+        let mut outputs = Vec::new();
+        let mut builder = None;
+        let merged = self.merge(canceled, &mut builder, &mut outputs);
+        if !matches!(merged, Ok(true)) {
+            if let Some((number, table_builder)) = builder.take() {
+                drop(table_builder);
+                let _ = fs::remove_file(self.tmp_table_path(number));
+            }
+            for table in &outputs {
+                let _ = fs::remove_file(&table.path);
+            }
+        }
+        Ok(merged?.then_some(outputs))
+    }
+
+    /// Writes the merged inputs out; false if canceled halfway.
+    fn merge(
+        &self,
+        canceled: &dyn Fn() -> bool,
+        builder: &mut Option<(u64, TableBuilder)>,
+        outputs: &mut Vec<Arc<Table>>,
+    ) -> Result<bool> {
+        let mut sources: Vec<EntryIterator> = self
+            .inputs
+            .iter()
+            .map(|t| Box::new(t.iter()) as EntryIterator)
+            .collect();
+        let overlapping = self.overlapping.clone();
+        sources.push(Box::new(overlapping.into_iter().flat_map(|t| t.iter())));
+
+        let mut versions: Vec<Entry> = Vec::new();
+        for entry in MergingIterator::new(sources) {
+            let entry = entry?;
+            if versions.first().is_some_and(|v| v.key != entry.key) {
+                if canceled() {
+                    return Ok(false);
+                }
+                let key_versions = std::mem::take(&mut versions);
+                self.write_versions(key_versions, builder, outputs)?;
+            }
+            versions.push(entry);
+        }
+        if !versions.is_empty() {
+            self.write_versions(versions, builder, outputs)?;
+        }
+        if let Some((number, table_builder)) = builder.take() {
+            outputs.push(self.finish_table(number, table_builder)?);
+        }
+        Ok(true)
+    }
+
+    /// Adds the versions of one key that are still visible to the output,
+    /// starting a new table first if needed and cutting it after them if it
+    /// is full.
+    fn write_versions(
+        &self,
+        mut versions: Vec<Entry>,
+        builder: &mut Option<(u64, TableBuilder)>,
+        outputs: &mut Vec<Arc<Table>>,
+    ) -> Result<()> {
+        let bottommost = self.is_bottommost(&versions[0].key);
+        self.retention.retain(&mut versions, bottommost);
+        if versions.is_empty() {
+            return Ok(());
+        }
+        if builder.is_none() {
+            let number = self.file_numbers.fetch_add(1, Ordering::Relaxed);
+            let tmp = self.tmp_table_path(number);
+            *builder = Some((
+                number,
+                TableBuilder::new(tmp, self.compression, &self.table_options)?,
+            ));
+        }
+        let (_, table_builder) = builder.as_mut().unwrap();
+        for entry in &versions {
+            table_builder.add(entry)?;
+        }
+        // Universal runs are single tables, so only leveled output is split.
+        if self.output_level > 0 && table_builder.estimated_size() >= TARGET_FILE_SIZE {
+            let (number, table_builder) = builder.take().unwrap();
+            outputs.push(self.finish_table(number, table_builder)?);
+        }
+        Ok(())
+    }
+
+    fn finish_table(&self, number: u64, builder: TableBuilder) -> Result<Arc<Table>> {
+        builder.finish()?;
+        let path = self.output_dir.join(table_file_name(number));
+        fs::rename(self.tmp_table_path(number), &path)?;
+        Ok(Arc::new(Table::open(&path, number, &self.table_options)?))
+    }
+
+    fn tmp_table_path(&self, number: u64) -> PathBuf {
+        self.output_dir
+            .join(format!("{}.tmp", table_file_name(number)))
+    }
+
+    /// True if no table older than the compaction output, other than its own
+    /// inputs, can hold data for `key`.
+    fn is_bottommost(&self, key: &Bytes) -> bool {
+        self.below.iter().all(|t| !t.overlaps(key, key))
+    }
+}
+
+impl SST {
+    /// How far over budget `level` is; anything at or above 1.0 needs compacting.
+    pub(crate) fn level_score(&self, level: usize) -> f64 {
+        if level + 1 >= MAX_LEVELS {
+            return 0.0;
+        }
+        let by_size = self.curr_level_size[level] as f64 / self.max_level_size[level] as f64;
+        if level == 0 {
+            by_size.max(self.tables[0].len() as f64 / L0_COMPACTION_TRIGGER as f64)
+        } else {
+            by_size
+        }
+    }
+
+    fn is_compacting(&self, table: &Table) -> bool {
+        self.compacting.contains(&table.file_number)
+    }
+
+    /// Picks the most needed compaction that no running one shares a table
+    /// with, and marks its tables as being compacted until
+    /// [`SST::finish_compaction`].
+    pub(crate) fn pick_compaction(&mut self) -> Result<Option<Compaction>> {
+        let picked = match self.options.compaction_strategy {
+            CompactionStrategy::Leveled => self.pick_leveled_compaction(),
+            CompactionStrategy::Universal => self.pick_universal_compaction(),
+        };
+        let Some((level, output_level, inputs, overlapping)) = picked else {
+            return Ok(None);
+        };
+        while self.tables.len() <= output_level {
+            self.add_level()?;
+        }
+        let compaction = Compaction {
+            level,
+            output_level,
+            below: Vec::new(),
+            output_dir: self.levels[output_level].clone(),
+            file_numbers: Arc::clone(&self.next_file_number),
+            compression: self.options.compression,
+            table_options: self.table_options.clone(),
+            retention: Retention::new(
+                self.snapshots.sequences(),
+                self.options.merge_operator.clone(),
+            ),
+            inputs,
+            overlapping,
+        };
+        let below = self.tables[output_level..]
+            .iter()
+            .flatten()
+            .filter(|t| !compaction.is_input(t))
+            .cloned()
+            .collect();
+        self.compacting
+            .extend(compaction.tables().map(|t| t.file_number));
+        Ok(Some(Compaction {
+            below,
+            ..compaction
+        }))
+    }
+
+    /// Levels over budget, most urgent first, are searched for a table to
+    /// compact that is free along with what it overlaps below.
+    fn pick_leveled_compaction(&mut self) -> Option<Picked> {
+        let mut levels: Vec<(usize, f64)> = (0..self.tables.len())
+            .map(|level| (level, self.level_score(level)))
+            .filter(|&(level, score)| score >= 1.0 && !self.tables[level].is_empty())
+            .collect();
+        levels.sort_by(|a, b| b.1.total_cmp(&a.1));
+        levels
+            .into_iter()
+            .find_map(|(level, _)| self.pick_from_level(level))
+    }
+
+    fn pick_from_level(&mut self, level: usize) -> Option<Picked> {
+        let output_level = level + 1;
+        let overlapping = |sst: &SST, inputs: &[Arc<Table>]| -> Option<Vec<Arc<Table>>> {
+            let smallest = inputs.iter().map(|t| &t.smallest_key).min()?;
+            let largest = inputs.iter().map(|t| &t.largest_key).max()?;
+            let overlapping: Vec<Arc<Table>> = sst
+                .tables
+                .get(output_level)
+                .map(|tables| {
+                    tables
+                        .iter()
+                        .filter(|t| t.overlaps(smallest, largest))
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default();
+            (!overlapping.iter().any(|t| sst.is_compacting(t))).then_some(overlapping)
+        };
+
+        let (inputs, overlapping) = if level == 0 {
+            // Level 0 tables overlap each other, so they all go at once and
+            // only one such compaction runs at a time.
+            if self.tables[0].iter().any(|t| self.is_compacting(t)) {
+                return None;
+            }
+            let inputs = self.tables[0].clone();
+            let overlapping = overlapping(self, &inputs)?;
+            (inputs, overlapping)
+        } else {
+            // Rotate through the level so every key range gets its turn.
+            let tables = &self.tables[level];
+            let next = match &self.compact_pointer[level] {
+                Some(pointer) => tables
+                    .iter()
+                    .position(|t| t.smallest_key > *pointer)
+                    .unwrap_or(0),
+                None => 0,
+            };
+            (0..tables.len())
+                .map(|i| &tables[(next + i) % tables.len()])
+                .filter(|t| !self.is_compacting(t))
+                .find_map(|t| {
+                    let inputs = vec![Arc::clone(t)];
+                    Some((inputs.clone(), overlapping(self, &inputs)?))
+                })?
+        };
+        let largest = inputs.iter().map(|t| &t.largest_key).max()?.clone();
+        self.compact_pointer[level] = Some(largest);
+        Some((level, output_level, inputs, overlapping))
+    }
+
+    fn pick_universal_compaction(&self) -> Option<Picked> {
+        let runs = &self.tables[0];
+        // Runs are merged newest first, so one merge at a time.
+        if runs.len() < L0_COMPACTION_TRIGGER || runs.iter().any(|t| self.is_compacting(t)) {
+            return None;
+        }
+        let sizes: Vec<u64> = runs.iter().map(|t| t.file_size).collect();
+        let oldest = *sizes.last()?;
+        let newer: u64 = sizes[..sizes.len() - 1].iter().sum();
+
+        let width = if newer * 100 >= oldest * UNIVERSAL_MAX_SIZE_AMPLIFICATION {
+            runs.len()
+        } else {
+            let mut merged = sizes[0];
+            let mut width = 1;
+            while width < sizes.len() && sizes[width] * 100 <= merged * (100 + UNIVERSAL_SIZE_RATIO)
+            {
+                merged += sizes[width];
+                width += 1;
+            }
+            if width >= UNIVERSAL_MIN_MERGE_WIDTH {
+                width
+            } else {
+                // No similar sized runs; merge just enough to get under the trigger.
+                runs.len() - L0_COMPACTION_TRIGGER + 2
+            }
+        };
+
+        Some((0, 0, runs[..width].to_vec(), Vec::new()))
+    }
+
+    /// Installs what `compaction` wrote, or with `None` just gives its
+    /// tables back, and releases them for other compactions.
+    pub(crate) fn finish_compaction(
+        &mut self,
+        compaction: &Compaction,
+        outputs: Option<Vec<Arc<Table>>>,
+    ) -> Result<()> {
+        for table in compaction.tables() {
+            self.compacting.remove(&table.file_number);
+        }
+        let Some(outputs) = outputs else {
+            return Ok(());
+        };
+        self.log_edit(compaction.edit(&outputs))?;
+        self.install(compaction, outputs);
+        if !compaction.is_trivial_move() {
+            for table in compaction.tables() {
+                fs::remove_file(&table.path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Swaps the compaction inputs for its outputs in the in-memory levels.
+    fn install(&mut self, compaction: &Compaction, outputs: Vec<Arc<Table>>) {
+        self.tables[compaction.level].retain(|t| !compaction.is_input(t));
+        let output_level = &mut self.tables[compaction.output_level];
+        output_level.retain(|t| !compaction.is_input(t));
+        output_level.extend(outputs);
+        if compaction.output_level == 0 {
+            // A flush may have landed while the merge ran, so the output is
+            // not necessarily the newest run even though its file number is.
+            sort_level0(output_level);
+        } else {
+            output_level.sort_by(|a, b| a.smallest_key.cmp(&b.smallest_key));
+        }
+
+        for level in [compaction.level, compaction.output_level] {
+            self.curr_level_size[level] = self.tables[level]
+                .iter()
+                .map(|t| t.file_size as usize)
+                .sum();
+        }
+    }
+}
+
+/// Level, output level, inputs and overlapping tables of a picked compaction.
+type Picked = (usize, usize, Vec<Arc<Table>>, Vec<Arc<Table>>);
+
+/// Decides which versions of a key a flush or compaction writes out.
+pub(crate) struct Retention {
+    /// Live snapshot sequence numbers, ascending.
+    snapshots: Vec<u64>,
+    /// Unix time in milliseconds the flush or compaction started at.
+    now: u64,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+}
+
+impl Retention {
+    pub(crate) fn new(snapshots: Vec<u64>, merge_operator: Option<Arc<dyn MergeOperator>>) -> Self {
+        Retention {
+            snapshots,
+            now: unix_millis(SystemTime::now()),
+            merge_operator,
+        }
+    }
+
+    /// Index of the oldest snapshot that can see version `seq`, or the
+    /// number of snapshots if none can. Versions sharing a stripe are seen
+    /// by exactly the same snapshots.
+    fn stripe(&self, seq: u64) -> usize {
+        self.snapshots.partition_point(|&s| s < seq)
+    }
+
+    /// Drops the versions of one key, given newest first, that no reader can
+    /// see any more: within a stripe only what its newest version reads as
+    /// matters, so merge operands are folded onto the put or delete below
+    /// them and everything older is dropped. An expired put reads as absent
+    /// to everyone, so it is kept as a tombstone without its value. When
+    /// nothing older than the output can hold the key (`bottommost`), its
+    /// oldest tombstones have nothing left to hide and go as well.
+    pub(crate) fn retain(&self, versions: &mut Vec<Entry>, bottommost: bool) {
+        for v in versions.iter_mut().filter(|v| v.is_expired(self.now)) {
+            v.kind = ValueKind::Delete;
+            v.expires_at = None;
+            v.value = Bytes::new();
+        }
+        let mut kept = Vec::with_capacity(versions.len());
+        let mut rest = std::mem::take(versions).into_iter().peekable();
+        while let Some(first) = rest.next() {
+            let stripe = self.stripe(first.seq);
+            let mut group = vec![first];
+            while let Some(v) = rest.next_if(|v| self.stripe(v.seq) == stripe) {
+                group.push(v);
+            }
+            let nothing_below = bottommost && rest.peek().is_none();
+            self.collapse(group, nothing_below, &mut kept);
+        }
+        if bottommost {
+            while kept.last().is_some_and(|v| v.kind == ValueKind::Delete) {
+                kept.pop();
+            }
+        }
+        *versions = kept;
+    }
+
+    /// Reduces the versions of one stripe, newest first, to the fewest that
+    /// read the same.
+    fn collapse(&self, group: Vec<Entry>, nothing_below: bool, out: &mut Vec<Entry>) {
+        // The newest put or delete shadows everything older in the stripe.
+        let (operands, base) = match group.iter().position(|v| v.kind != ValueKind::Merge) {
+            Some(i) => (&group[..i], Some(&group[i])),
+            None => (&group[..], None),
+        };
+        if operands.is_empty() {
+            out.extend(base.cloned());
+            return;
+        }
+        // Folding onto a put that has yet to expire would make the result
+        // expire with it, so such a base is kept apart.
+        let foldable = match base {
+            Some(base) => base.expires_at.is_none(),
+            None => nothing_below,
+        };
+        if foldable {
+            let existing = base
+                .filter(|b| b.kind == ValueKind::Put)
+                .map(|b| b.value.as_ref());
+            let values: Vec<Bytes> = operands.iter().map(|o| o.value.clone()).collect();
+            let operator = self.merge_operator.as_deref();
+            // On failure the operands are kept, for reads to report the error.
+            if let Ok(value) = full_merge(operator, &operands[0].key, existing, &values) {
+                out.push(Entry {
+                    kind: ValueKind::Put,
+                    expires_at: None,
+                    value: Bytes::from(value),
+                    ..operands[0].clone()
+                });
+                return;
+            }
+        }
+        self.partial_merge(operands, out);
+        out.extend(base.cloned());
+    }
+
+    /// Combines neighbouring operands, given newest first, wherever the
+    /// merge operator can.
+    fn partial_merge(&self, operands: &[Entry], out: &mut Vec<Entry>) {
+        let Some(operator) = self.merge_operator.as_deref() else {
+            out.extend_from_slice(operands);
+            return;
+        };
+        let mut merged: Vec<Entry> = Vec::new();
+        for operand in operands.iter().rev() {
+            if let Some(older) = merged.last_mut() {
+                if let Some(value) =
+                    operator.partial_merge(&operand.key, &older.value, &operand.value)
+                {
+                    *older = Entry {
+                        value: Bytes::from(value),
+                        ..operand.clone()
+                    };
+                    continue;
+                }
+            }
+            merged.push(operand.clone());
+        }
+        out.extend(merged.into_iter().rev());
+    }
+}
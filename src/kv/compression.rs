@@ -0,0 +1,279 @@
+//! Data block compression.
+//!
+//! Every data block is written as `[payload][codec u8]`, where the codec id
+//! names the [`Compressor`] that produced the payload. The codec is picked
+//! per block, so a table can mix codecs and changing
+//! [`crate::Options::compression`] never makes existing files unreadable.
+//! Ids below 128 belong to the built-in codecs; the rest are left to codecs
+//! registered in [`crate::Options::compressors`].
+
+use super::options::{CompressionType, Options};
+use crate::errors::{Result, ShortDBErrors};
+use bytes::Bytes;
+use std::fmt;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+/// A block is only stored compressed if that saves at least 1/8 of it;
+/// otherwise it is not worth the decompression on every read.
+const MIN_SAVINGS_DIVISOR: usize = 8;
+
+/// Lowest codec id a registered codec may take.
+pub(crate) const FIRST_CUSTOM_ID: u8 = 128;
+
+/// A block compression codec.
+///
+/// Besides the built-in codecs, any number can be registered through
+/// [`crate::Options::compressors`] and picked with
+/// [`CompressionType::Custom`]. Every block records the id of its codec, so
+/// a codec has to stay registered under the same id for as long as any
+/// table holds blocks it compressed.
+pub trait Compressor: Send + Sync {
+    /// Identifies the codec in errors.
+    fn name(&self) -> &str;
+
+    /// Id stored after every block the codec compressed, from 128 to 255
+    /// for a registered codec. Never change it once blocks were written.
+    fn id(&self) -> u8;
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>>;
+
+    /// Undoes `compress`. Data that cannot be decompressed should be
+    /// reported as `CorruptedSST`.
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+impl fmt::Debug for dyn Compressor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Compressor({}, {})", self.name(), self.id())
+    }
+}
+
+impl CompressionType {
+    /// Id stored after every data block. Never reuse or renumber these.
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            CompressionType::None => 0,
+            CompressionType::Deflate => 1,
+            #[cfg(feature = "lz4")]
+            CompressionType::Lz4 => 2,
+            #[cfg(feature = "zstd")]
+            CompressionType::Zstd => 3,
+            #[cfg(feature = "snappy")]
+            CompressionType::Snappy => 4,
+            CompressionType::Custom(id) => id,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(CompressionType::None),
+            1 => Ok(CompressionType::Deflate),
+            #[cfg(feature = "lz4")]
+            2 => Ok(CompressionType::Lz4),
+            #[cfg(feature = "zstd")]
+            3 => Ok(CompressionType::Zstd),
+            #[cfg(feature = "snappy")]
+            4 => Ok(CompressionType::Snappy),
+            id if id >= FIRST_CUSTOM_ID => Ok(CompressionType::Custom(id)),
+            other => Err(ShortDBErrors::UnsupportedCompression(other)),
+        }
+    }
+
+    /// The built-in codec; custom ones are looked up in [`Codecs`].
+    fn builtin(self) -> Option<&'static dyn Compressor> {
+        Some(match self {
+            CompressionType::None => &NoCompression,
+            CompressionType::Deflate => &Deflate,
+            #[cfg(feature = "lz4")]
+            CompressionType::Lz4 => &Lz4,
+            #[cfg(feature = "zstd")]
+            CompressionType::Zstd => &Zstd,
+            #[cfg(feature = "snappy")]
+            CompressionType::Snappy => &Snappy,
+            CompressionType::Custom(_) => return None,
+        })
+    }
+}
+
+/// The codecs a database can write and read: the built-in ones and those
+/// registered in its options.
+#[derive(Clone, Default)]
+pub(crate) struct Codecs {
+    custom: Vec<Arc<dyn Compressor>>,
+}
+
+impl Codecs {
+    pub(crate) fn new(options: &Options) -> Self {
+        Codecs {
+            custom: options.compressors.clone(),
+        }
+    }
+
+    fn get(&self, codec: CompressionType) -> Result<&dyn Compressor> {
+        let id = codec.id();
+        codec
+            .builtin()
+            .or_else(|| {
+                self.custom
+                    .iter()
+                    .find(|c| c.id() == id)
+                    .map(|c| c.as_ref())
+            })
+            .ok_or(ShortDBErrors::UnsupportedCompression(id))
+    }
+
+    /// Compresses a finished data block and appends its codec id.
+    pub(crate) fn encode_block(
+        &self,
+        data: Vec<u8>,
+        compression: CompressionType,
+    ) -> Result<Vec<u8>> {
+        let (mut out, codec) = match compression {
+            CompressionType::None => (data, CompressionType::None),
+            codec => {
+                let compressed = self.get(codec)?.compress(&data)?;
+                if compressed.len() <= data.len() - data.len() / MIN_SAVINGS_DIVISOR {
+                    (compressed, codec)
+                } else {
+                    (data, CompressionType::None)
+                }
+            }
+        };
+        out.push(codec.id());
+        Ok(out)
+    }
+
+    /// Strips the codec id off a data block read from disk and decompresses
+    /// it. An uncompressed block stays a slice of `data`.
+    pub(crate) fn decode_block(&self, data: Bytes) -> Result<Bytes> {
+        let (&id, payload) = data
+            .split_last()
+            .ok_or_else(|| ShortDBErrors::CorruptedSST("empty data block".to_string()))?;
+        match CompressionType::from_id(id)? {
+            CompressionType::None => Ok(data.slice(..payload.len())),
+            codec => self.get(codec)?.decompress(payload).map(Bytes::from),
+        }
+    }
+}
+
+fn corrupted(codec: &str, e: impl std::fmt::Display) -> ShortDBErrors {
+    ShortDBErrors::CorruptedSST(format!("bad {} block: {}", codec, e))
+}
+
+struct NoCompression;
+
+impl Compressor for NoCompression {
+    fn name(&self) -> &str {
+        "none"
+    }
+
+    fn id(&self) -> u8 {
+        CompressionType::None.id()
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+}
+
+struct Deflate;
+
+impl Compressor for Deflate {
+    fn name(&self) -> &str {
+        "deflate"
+    }
+
+    fn id(&self) -> u8 {
+        CompressionType::Deflate.id()
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data)?;
+        Ok(encoder.finish()?)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        flate2::read::DeflateDecoder::new(data)
+            .read_to_end(&mut out)
+            .map_err(|e| corrupted("deflate", e))?;
+        Ok(out)
+    }
+}
+
+#[cfg(feature = "lz4")]
+struct Lz4;
+
+#[cfg(feature = "lz4")]
+impl Compressor for Lz4 {
+    fn name(&self) -> &str {
+        "lz4"
+    }
+
+    fn id(&self) -> u8 {
+        CompressionType::Lz4.id()
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(lz4_flex::compress_prepend_size(data))
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        lz4_flex::decompress_size_prepended(data).map_err(|e| corrupted("lz4", e))
+    }
+}
+
+#[cfg(feature = "zstd")]
+struct Zstd;
+
+#[cfg(feature = "zstd")]
+impl Compressor for Zstd {
+    fn name(&self) -> &str {
+        "zstd"
+    }
+
+    fn id(&self) -> u8 {
+        CompressionType::Zstd.id()
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL)?)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        zstd::decode_all(data).map_err(|e| corrupted("zstd", e))
+    }
+}
+
+#[cfg(feature = "snappy")]
+struct Snappy;
+
+#[cfg(feature = "snappy")]
+impl Compressor for Snappy {
+    fn name(&self) -> &str {
+        "snappy"
+    }
+
+    fn id(&self) -> u8 {
+        CompressionType::Snappy.id()
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        snap::raw::Encoder::new()
+            .compress_vec(data)
+            .map_err(|e| ShortDBErrors::Io(std::io::Error::other(e)))
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        snap::raw::Decoder::new()
+            .decompress_vec(data)
+            .map_err(|e| corrupted("snappy", e))
+    }
+}
@@ -1,120 +1,544 @@
 use super::{
-    memtable::Memtable,
-    sst::SST,
+    batch::WriteBatch,
+    block::{unix_millis, ValueKind},
+    column_family::{ColumnFamilies, ColumnFamily, DEFAULT_COLUMN_FAMILY, DEFAULT_ID},
+    commit::{CommitQueue, WriteStats},
+    flush::Flusher,
+    iterator::{key_range, prefix_range, DBIterator, KeyRange},
+    lock::LockManager,
+    options::Options,
+    scheduler::CompactionScheduler,
+    snapshot::Snapshot,
+    stall::{Stall, StallStats, SLOWDOWN_DELAY, STOP_POLL_INTERVAL},
+    transaction::{OptimisticTransaction, PessimisticTransaction},
     wal::{WALEntry, WAL},
 };
 use crate::errors::{Result, ShortDBErrors};
-use bytes::Bytes;
-use std::path::{Path, PathBuf};
-use std::{fs, io::Read};
+use parking_lot::{Mutex, MutexGuard, RwLock};
+use std::fs;
+use std::iter::Rev;
+use std::ops::RangeBounds;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
+/// An open database. It is `Send + Sync` and every method takes `&self`,
+/// so one `Arc<ShorterDB>` can be shared by any number of threads.
+///
+/// Writers take turns on the WAL, in groups that share one append and one
+/// sync, while readers never wait for them: a
+/// write is published to readers only once it is in its Memtable, and
+/// readers skip anything newer than what was published when they started.
+/// Full Memtables are written to SST by a background thread and compacted
+/// by a pool of others, so neither waits for a flush or compaction either.
 pub struct ShorterDB {
-    pub(crate) memtable: Memtable,
-    pub(crate) wal: WAL,
-    pub(crate) sst: SST,
-    pub(crate) data_dir: PathBuf,
+    /// Held while a write is logged and applied, which serializes writers.
+    pub(crate) wal: Mutex<WAL>,
+    /// Writes waiting for the leader of their group to log them.
+    pub(crate) commits: CommitQueue,
+    pub(crate) sync_writes: bool,
+    pub(crate) families: RwLock<ColumnFamilies>,
+    /// Sequence number of the last published write, shared by every column
+    /// family.
+    pub(crate) last_sequence: AtomicU64,
+    pub(crate) locks: Arc<LockManager>,
+    pub(crate) flusher: Flusher,
+    pub(crate) compactions: Arc<CompactionScheduler>,
+    pub(crate) write_stall_timeout: Duration,
+    pub(crate) stalls: Mutex<StallStats>,
 }
 
 impl ShorterDB {
     pub fn new<P: AsRef<Path>>(data_dir: P) -> Result<Self> {
+        Self::new_with_options(data_dir, Options::default())
+    }
+
+    /// Opens or creates the database at `data_dir` with the given options.
+    /// Reopening with a different compaction strategy than the database was
+    /// created with fails with `CompactionStrategyMismatch`.
+    pub fn new_with_options<P: AsRef<Path>>(data_dir: P, options: Options) -> Result<Self> {
+        Self::new_with_column_families(data_dir, options, Vec::new())
+    }
+
+    /// Opens or creates the database at `data_dir`, with `options` for the
+    /// default column family and the column families in `families`, each
+    /// with its own options. Listed families that do not exist yet are
+    /// created; a family the database has but `families` leaves out makes
+    /// opening fail with `UnopenedColumnFamily`.
+    pub fn new_with_column_families<P: AsRef<Path>>(
+        data_dir: P,
+        options: Options,
+        families: Vec<(&str, Options)>,
+    ) -> Result<Self> {
         let data_dir = data_dir.as_ref().to_path_buf();
-        fs::create_dir_all(&data_dir); // Ensure the data directory exists
+        fs::create_dir_all(&data_dir)?; // Ensure the data directory exists
 
-        let wal = WAL::new(&data_dir).unwrap();
-        let sst = SST::open(format!("{:?}", data_dir));
+        let compactions = Arc::new(CompactionScheduler::start(
+            options.max_background_compactions,
+        ));
+        let write_stall_timeout = options.write_stall_timeout;
+        let sync_writes = options.sync_writes;
+        let families = ColumnFamilies::open(&data_dir, options, families)?;
+        let wal = WAL::new(&data_dir)?;
 
-        Ok(Self {
-            memtable: Memtable::new(),
-            wal,
-            sst,
-            data_dir,
-        })
+        let mut db = Self {
+            wal: Mutex::new(wal),
+            commits: CommitQueue::default(),
+            sync_writes,
+            last_sequence: AtomicU64::new(families.last_sequence()),
+            families: RwLock::new(families),
+            locks: Arc::new(LockManager::default()),
+            flusher: Flusher::start(Arc::clone(&compactions)),
+            compactions,
+            write_stall_timeout,
+            stalls: Mutex::new(StallStats::default()),
+        };
+        db.recover_wal()?;
+        for family in db.families.read().all() {
+            db.compactions.schedule(family);
+        }
+        Ok(db)
     }
 
-    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        // First check in Memtable
-        // if let Some(value) = self.memtable.get(key) {
-        //     return Ok(Some(value));
-        // }
-        //
-
-        match self.memtable.get(key) {
-            Ok(None) => println!("data deleted"),
-            Ok(Some(v)) => {
-                return Ok(Some(v.to_vec()));
+    /// Replays writes that were logged but never flushed into fresh
+    /// Memtables, flushing along the way if they overflow.
+    fn recover_wal(&mut self) -> Result<()> {
+        let wal = self.wal.get_mut();
+        let families = self.families.get_mut();
+        let last_sequence = self.last_sequence.get_mut();
+        let mut flushed = false;
+        for (seq, entry) in wal.read_entries()? {
+            *last_sequence = (*last_sequence).max(seq);
+            if families.insert(seq, &entry)? {
+                families.flush(entry.cf, *last_sequence)?;
+                flushed = true;
             }
-            Err(ShortDBErrors::KeyNotFound) => println!("not found in mem"),
-            Err(e) => println!("something problematic happend {}", e),
         }
 
-        // If not found in Memtable, check SST
-        if let Some(value) = self.sst.get(key) {
-            print!("checking in sst");
-            return Ok(Some(value));
+        if flushed || wal.has_sealed_segments()? {
+            // The head of the log is safely in SSTs now; keep only the tail
+            // that still lives in the Memtables alone, all in `wal.log`.
+            let pending = families.pending_writes();
+            if pending.is_empty() {
+                wal.truncate()?;
+            } else {
+                wal.rewrite(&pending)?;
+            }
+            wal.remove_sealed_segments()?;
         }
+        Ok(())
+    }
 
-        Err(ShortDBErrors::KeyNotFound) // Return None if not found
+    /// Blocks until every flush scheduled so far has finished, and then
+    /// until no compaction is running or, unless compactions are paused,
+    /// needed. Fails with `BackgroundError` if one of them failed.
+    pub fn wait_for_background_work(&self) -> Result<()> {
+        self.flusher.wait()?;
+        self.compactions.wait()
     }
 
-    pub fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
-        // Create a new WALEntry
-        let entry = WALEntry {
-            key: Bytes::copy_from_slice(key),
-            value: Bytes::copy_from_slice(value),
-        };
+    /// How often and for how long writes were held back because flushes
+    /// or compactions fell behind, and why.
+    pub fn stall_stats(&self) -> StallStats {
+        *self.stalls.lock()
+    }
+
+    /// How many writes were logged, in how many WAL appends and syncs.
+    pub fn write_stats(&self) -> WriteStats {
+        self.commits.stats()
+    }
 
-        // Write to the WAL
-        self.wal.write(&entry);
+    /// Stops new compactions from starting; running ones still finish.
+    pub fn pause_compactions(&self) {
+        self.compactions.pause();
+    }
 
-        // Insert into Memtable
-        self.memtable.set(key, value)?;
+    /// Lets compactions start again after [`ShorterDB::pause_compactions`]
+    /// or [`ShorterDB::cancel_compactions`].
+    pub fn resume_compactions(&self) {
+        self.compactions.resume();
+    }
 
-        // Check if we need to flush Memtable to SST
-        if let Err(err) = self.memtable.set(key, value) {
-            match err {
-                ShortDBErrors::FlushNeededFromMemTable => self.flush_memtable()?,
-                _ => println!("some err happend"),
-            }
+    /// Pauses compactions and stops the running ones, throwing away what
+    /// they wrote so far. Returns once they have stopped.
+    pub fn cancel_compactions(&self) {
+        self.compactions.cancel();
+    }
+
+    /// Sequence number of the newest write readers may see.
+    fn published_sequence(&self) -> u64 {
+        self.last_sequence.load(Ordering::Acquire)
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_latest(self.families.read().default_family(), key)
+    }
+
+    /// Like [`ShorterDB::get`], in column family `cf`.
+    pub fn get_cf(&self, cf: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_latest(self.families.read().named(cf)?, key)
+    }
+
+    fn get_latest(&self, family: &RwLock<ColumnFamily>, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let family = family.read();
+        // Read the sequence number under the family lock: no flush can
+        // have dropped a version it sees.
+        family.get_at(key, self.published_sequence())
+    }
+
+    /// Looks `key` up in the default column family as of sequence number
+    /// `seq`. A deleted or expired key is reported as `Ok(None)` and a key
+    /// that was never written as `KeyNotFound`.
+    pub(crate) fn get_at(&self, key: &[u8], seq: u64) -> Result<Option<Vec<u8>>> {
+        self.families
+            .read()
+            .default_family()
+            .read()
+            .get_at(key, seq)
+    }
+
+    /// Sequence number of the newest version of `key`, tombstones included,
+    /// or `None` if it was never written.
+    pub(crate) fn latest_sequence(&self, key: &[u8]) -> Result<Option<u64>> {
+        let families = self.families.read();
+        let newest = families.default_family().read().entry_at(key, u64::MAX)?;
+        Ok(newest.map(|e| e.seq))
+    }
+
+    /// Starts an optimistic transaction. It reads from a snapshot taken now,
+    /// buffers its writes, and only checks for conflicting writes when it
+    /// commits.
+    pub fn begin_optimistic(&self) -> OptimisticTransaction {
+        OptimisticTransaction::new(self.snapshot())
+    }
+
+    /// Starts a pessimistic transaction, which locks the keys it reads for
+    /// update or writes until it commits or rolls back.
+    pub fn begin_pessimistic(&self) -> PessimisticTransaction {
+        PessimisticTransaction::new(Arc::clone(&self.locks))
+    }
+
+    /// Takes a consistent, read-only view of the database as it is now.
+    /// Versions it can see survive flushes and compactions until it is dropped.
+    pub fn snapshot(&self) -> Snapshot {
+        let snapshots = self.families.read().snapshots.clone();
+        Snapshot::latest(&self.last_sequence, snapshots)
+    }
+
+    /// Iterates every live key in ascending order.
+    pub fn iter(&self) -> DBIterator {
+        self.range::<&[u8], _>(..)
+    }
+
+    /// Iterates the live keys within `range` in ascending order, e.g.
+    /// `db.range("user:100".."user:200")`.
+    pub fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> DBIterator {
+        self.scan_latest(self.families.read().default_family(), key_range(range))
+    }
+
+    /// Scans `range` of the default column family as of sequence number
+    /// `seq`.
+    pub(crate) fn scan_at(&self, range: KeyRange, seq: u64) -> DBIterator {
+        self.families
+            .read()
+            .default_family()
+            .read()
+            .scan_at(range, seq)
+    }
+
+    fn scan_latest(&self, family: &RwLock<ColumnFamily>, range: KeyRange) -> DBIterator {
+        let family = family.read();
+        family.scan_at(range, self.published_sequence())
+    }
+
+    /// Iterates the live keys starting with `prefix` in ascending order.
+    /// Reverse it to read the last entries under a prefix first.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> DBIterator {
+        self.scan_latest(self.families.read().default_family(), prefix_range(prefix))
+    }
+
+    /// Iterates backwards from the last live key `<= key`.
+    pub fn seek_for_prev(&self, key: &[u8]) -> Rev<DBIterator> {
+        self.range(..=key).rev()
+    }
+
+    /// Like [`ShorterDB::iter`], in column family `cf`.
+    pub fn iter_cf(&self, cf: &str) -> Result<DBIterator> {
+        self.range_cf::<&[u8], _>(cf, ..)
+    }
+
+    /// Like [`ShorterDB::range`], in column family `cf`.
+    pub fn range_cf<K: AsRef<[u8]>, R: RangeBounds<K>>(
+        &self,
+        cf: &str,
+        range: R,
+    ) -> Result<DBIterator> {
+        let families = self.families.read();
+        Ok(self.scan_latest(families.named(cf)?, key_range(range)))
+    }
+
+    /// Like [`ShorterDB::scan_prefix`], in column family `cf`.
+    pub fn scan_prefix_cf(&self, cf: &str, prefix: &[u8]) -> Result<DBIterator> {
+        let families = self.families.read();
+        Ok(self.scan_latest(families.named(cf)?, prefix_range(prefix)))
+    }
+
+    /// Names of every column family, `default` first.
+    pub fn column_families(&self) -> Vec<String> {
+        self.families.read().names()
+    }
+
+    /// Adds a column family with its own options. Fails with
+    /// `ColumnFamilyExists` if the name is taken.
+    pub fn create_column_family(&self, name: &str, options: Options) -> Result<()> {
+        self.families.write().create(name, options)
+    }
+
+    /// Removes a column family and deletes all of its data. The default
+    /// family cannot be dropped.
+    pub fn drop_column_family(&self, name: &str) -> Result<()> {
+        self.families.write().remove(name)
+    }
+
+    pub fn set(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.apply(DEFAULT_ID, WALEntry::put(key, value))
+    }
+
+    /// Sets `key` to `value` for `ttl`; after that it reads as absent and
+    /// compaction drops it. A `ttl` too long to represent never runs out.
+    pub fn set_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        let expires_at = SystemTime::now()
+            .checked_add(ttl)
+            .map_or(u64::MAX, unix_millis);
+        self.apply(DEFAULT_ID, WALEntry::put_expiring(key, value, expires_at))
+    }
+
+    /// Makes the current value of `key` expire at `when`, replacing any
+    /// expiry it had. Fails with `KeyNotFound` if `key` has no live value.
+    pub fn expire_at(&self, key: &[u8], when: SystemTime) -> Result<()> {
+        let mut wal = self.lock_writes();
+        let value = self.get(key)?.ok_or(ShortDBErrors::KeyNotFound)?;
+        let entry = WALEntry::put_expiring(key, &value, unix_millis(when));
+        self.log_and_apply(&mut wal, &[&[entry]])
+    }
+
+    /// Records `operand` for `key` without reading it. Reads fold the
+    /// operands onto the value with [`Options::merge_operator`], which must
+    /// be set.
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.merge_cf(DEFAULT_COLUMN_FAMILY, key, operand)
+    }
+
+    /// Replaces the value of `key` with `new` only if it currently is
+    /// `expected`, where `None` stands for an absent key on either side:
+    /// `expected: None` requires the key to be absent and `new: None` deletes
+    /// it. Returns whether the write happened; nothing is logged if not.
+    pub fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool> {
+        // Hold the write lock so nothing can slip in between the read and
+        // the write.
+        let mut wal = self.lock_writes();
+        let current = match self.get(key) {
+            Err(ShortDBErrors::KeyNotFound) => None,
+            other => other?,
+        };
+        if current.as_deref() != expected {
+            return Ok(false);
         }
+        let entry = match new {
+            Some(value) => WALEntry::put(key, value),
+            None => WALEntry::delete(key),
+        };
+        self.log_and_apply(&mut wal, &[&[entry]])?;
+        Ok(true)
+    }
 
-        Ok(())
+    /// Sets `key` to `value` unless it already has a live value. Returns
+    /// whether the write happened.
+    pub fn put_if_absent(&self, key: &[u8], value: &[u8]) -> Result<bool> {
+        self.compare_and_swap(key, None, Some(value))
+    }
+
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        // Deleting a key writes a tombstone version of it
+        self.apply(DEFAULT_ID, WALEntry::delete(key))
+    }
+
+    /// Like [`ShorterDB::set`], in column family `cf`.
+    pub fn set_cf(&self, cf: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        let cf = self.families.read().id(cf)?;
+        self.apply(cf, WALEntry::put(key, value))
     }
 
-    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
-        // Create a tombstone entry
-        let tombstone_entry = WALEntry {
-            key: Bytes::copy_from_slice(key),
-            value: Bytes::copy_from_slice(b"tombstone"),
+    /// Like [`ShorterDB::merge`], in column family `cf`, whose options must
+    /// set a merge operator.
+    pub fn merge_cf(&self, cf: &str, key: &[u8], operand: &[u8]) -> Result<()> {
+        let id = {
+            let families = self.families.read();
+            let id = families.id(cf)?;
+            families.check_merge_operator(id)?;
+            id
         };
+        self.apply(id, WALEntry::merge(key, operand))
+    }
+
+    /// Like [`ShorterDB::delete`], in column family `cf`.
+    pub fn delete_cf(&self, cf: &str, key: &[u8]) -> Result<()> {
+        let cf = self.families.read().id(cf)?;
+        self.apply(cf, WALEntry::delete(key))
+    }
 
-        // Write tombstone to WAL
-        self.wal.write(&tombstone_entry);
+    /// Logs a single write to column family `cf`.
+    fn apply(&self, cf: u32, entry: WALEntry) -> Result<()> {
+        self.commit(vec![WALEntry { cf, ..entry }])
+    }
 
-        // Delete from Memtable
-        self.memtable.delete(key)?;
+    /// Applies every write in `batch` atomically: it is logged as one WAL
+    /// record, so a crash never leaves part of it visible. Fails with
+    /// `UnknownColumnFamily`, writing nothing, if it names a column family
+    /// the database does not have, and with `NoMergeOperator` if it merges
+    /// into a family without a merge operator.
+    pub fn write(&self, batch: WriteBatch) -> Result<()> {
+        self.commit(self.resolve(batch)?)
+    }
+
+    /// Takes the write lock. No other write is logged while the guard is
+    /// held, so a caller can check the database and write based on what it
+    /// saw.
+    pub(crate) fn lock_writes(&self) -> MutexGuard<'_, WAL> {
+        self.wal.lock()
+    }
 
-        // Check if we need to flush Memtable to SST
-        if let Err(err) = self.memtable.delete(key) {
-            match err {
-                ShortDBErrors::FlushNeededFromMemTable => self.flush_memtable()?,
-                _er => println!("some problem: {}", _er),
+    /// Like [`ShorterDB::write`], under a write lock the caller holds.
+    pub(crate) fn write_locked(&self, wal: &mut WAL, batch: WriteBatch) -> Result<()> {
+        let entries = self.resolve(batch)?;
+        self.log_and_apply(wal, &[&entries])
+    }
+
+    /// Tags every write in `batch` with the id of its column family, and
+    /// checks that the families of its merges have a merge operator.
+    fn resolve(&self, batch: WriteBatch) -> Result<Vec<WALEntry>> {
+        let families = self.families.read();
+        batch
+            .entries
+            .into_iter()
+            .map(|(cf, entry)| {
+                let cf = match cf {
+                    Some(name) => families.id(&name)?,
+                    None => DEFAULT_ID,
+                };
+                if entry.kind == ValueKind::Merge {
+                    families.check_merge_operator(cf)?;
+                }
+                Ok(WALEntry { cf, ..entry })
+            })
+            .collect()
+    }
+
+    /// Queues `entries` to be logged as one record. Unless another writer
+    /// leads the group they join, and wakes this one with the result, takes
+    /// the write lock and logs them together with every other write queued
+    /// by then.
+    fn commit(&self, entries: Vec<WALEntry>) -> Result<()> {
+        let ticket = self.commits.push(entries);
+        if let Some(result) = ticket.wait() {
+            return result;
+        }
+        let mut wal = self.lock_writes();
+        let group = self.commits.take();
+        let writes: Vec<&[WALEntry]> = group.iter().map(|w| w.entries.as_slice()).collect();
+        let result = self.log_and_apply(&mut wal, &writes);
+        for write in &group {
+            write.finish(&result);
+        }
+        self.commits.hand_off();
+        result
+    }
+
+    /// Logs each of `writes` as one WAL record, all with a single append,
+    /// inserts them into their Memtables and publishes them together, then
+    /// hands the Memtables to the flush thread if they filled one.
+    fn log_and_apply(&self, wal: &mut WAL, writes: &[&[WALEntry]]) -> Result<()> {
+        self.flusher.check()?;
+        self.compactions.check()?;
+        self.throttle()?;
+        // Only writers move the sequence number, and they hold `wal`.
+        let first_seq = self.last_sequence.load(Ordering::Relaxed) + 1;
+        let mut next_seq = first_seq;
+        let mut records = Vec::with_capacity(writes.len());
+        for entries in writes {
+            records.push((next_seq, *entries));
+            next_seq += entries.len() as u64;
+        }
+        if next_seq == first_seq {
+            return Ok(());
+        }
+        let last_seq = next_seq - 1;
+        wal.write_group(&records, self.sync_writes)?;
+        let logged = records.iter().filter(|(_, e)| !e.is_empty()).count();
+        self.commits.record(logged, self.sync_writes);
+
+        let families = self.families.read();
+        // Flush once the whole group is in, never halfway through a batch.
+        let mut full = false;
+        for (first_seq, entries) in records {
+            for (seq, entry) in (first_seq..).zip(entries) {
+                full |= families.insert(seq, entry)?;
             }
         }
+        // Readers see all of the writes or none of them.
+        self.last_sequence.store(last_seq, Ordering::Release);
 
+        if full {
+            // Freeze every family with writes, not just the full one, so the
+            // sealed segment can go as soon as this generation is flushed.
+            let sealed = wal.roll()?;
+            let mut jobs = families.freeze(last_seq);
+            if let Some(last) = jobs.last_mut() {
+                last.release = Some(sealed);
+            }
+            self.flusher.schedule(jobs);
+        }
         Ok(())
     }
 
-    fn flush_memtable(&mut self) -> Result<()> {
-        // Flush entries from Memtable to SST
-        // for entry in self.memtable.memtable.iter() {
-        //     self.sst.set(entry.key().as_ref(), entry.value().as_ref())?;
-        // }
-        self.sst.queue.push_back(self.memtable.clone());
-        self.sst.set();
-
-        // Clear the Memtable after flushing
-        self.memtable.clear();
-
+    /// Holds the write back while flushes or compactions are too far
+    /// behind: briefly once past a slowdown threshold, and for as long as it
+    /// takes past a stop threshold, up to `write_stall_timeout`. Writers
+    /// take turns, so the ones behind this one wait as well.
+    fn throttle(&self) -> Result<()> {
+        let started = Instant::now();
+        let mut worst = None;
+        loop {
+            let stall = self.families.read().stall();
+            match stall {
+                None => break,
+                Some(Stall::Delay(_)) => {
+                    worst = worst.or(stall);
+                    thread::sleep(SLOWDOWN_DELAY);
+                    break;
+                }
+                Some(Stall::Stop(reason)) => {
+                    worst = stall;
+                    if started.elapsed() >= self.write_stall_timeout {
+                        let mut stalls = self.stalls.lock();
+                        stalls.record(Stall::Stop(reason), started.elapsed());
+                        stalls.timed_out_writes += 1;
+                        return Err(ShortDBErrors::WriteStall(reason));
+                    }
+                    thread::sleep(STOP_POLL_INTERVAL);
+                }
+            }
+        }
+        if let Some(stall) = worst {
+            self.stalls.lock().record(stall, started.elapsed());
+        }
         Ok(())
     }
 }
@@ -0,0 +1,142 @@
+//! Background flushes.
+//!
+//! When a write fills a Memtable, the writer freezes the Memtable of every
+//! family that has writes, seals the WAL segment holding them and hands the
+//! frozen Memtables to the flush thread. Reads keep finding them in the
+//! queue of their SST, and writes go on into fresh Memtables and a fresh
+//! segment, while the thread writes them out one by one, oldest first. Once
+//! the last Memtable of a generation is in its table, the sealed segment
+//! holds nothing the database still needs and is deleted. Every flush
+//! queues its family for the compaction workers.
+//!
+//! A flush that fails leaves its Memtable in the queue and its segment on
+//! disk, so the writes are still read and replayed on the next open. No
+//! later flush runs, since it would mark those writes as flushed, and every
+//! write from then on fails with [`ShortDBErrors::BackgroundError`].
+
+use super::{column_family::ColumnFamily, memtable::Memtable, scheduler::CompactionScheduler};
+use crate::errors::{Result, ShortDBErrors};
+use crossbeam_channel::{unbounded, Sender};
+use parking_lot::{Condvar, Mutex, RwLock};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// One frozen Memtable to write out.
+pub(crate) struct FlushJob {
+    pub(crate) family: Arc<RwLock<ColumnFamily>>,
+    pub(crate) memtable: Arc<Memtable>,
+    /// Last write logged before the Memtable was frozen.
+    pub(crate) last_sequence: u64,
+    /// Sealed WAL segment to delete after this job, the last of its
+    /// generation.
+    pub(crate) release: Option<PathBuf>,
+}
+
+impl FlushJob {
+    fn run(self, compactions: &CompactionScheduler) -> Result<()> {
+        // A dropped family needs no table, but the segment still goes.
+        let pending = {
+            let mut family = self.family.write();
+            (!family.dropped).then(|| family.sst.prepare_flush())
+        };
+        if let Some(pending) = pending {
+            // The slow part: readers and writers carry on meanwhile.
+            let written = pending.write(&self.memtable);
+
+            let mut family = self.family.write();
+            if !family.dropped {
+                family.sst.install_flush(written?, self.last_sequence)?;
+                drop(family);
+                compactions.schedule(&self.family);
+            }
+        }
+        if let Some(segment) = self.release {
+            fs::remove_file(segment)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct FlushState {
+    /// Jobs scheduled but not finished yet.
+    pending: Mutex<usize>,
+    idle: Condvar,
+    /// What made the first failed flush fail.
+    error: Mutex<Option<String>>,
+}
+
+/// Handle on the flush thread. Dropping it lets the thread finish every
+/// job scheduled so far and waits for it.
+pub(crate) struct Flusher {
+    sender: Option<Sender<FlushJob>>,
+    thread: Option<JoinHandle<()>>,
+    state: Arc<FlushState>,
+}
+
+impl Flusher {
+    pub(crate) fn start(compactions: Arc<CompactionScheduler>) -> Self {
+        let (sender, receiver) = unbounded::<FlushJob>();
+        let state = Arc::new(FlushState::default());
+        let thread = {
+            let state = Arc::clone(&state);
+            thread::spawn(move || {
+                for job in receiver {
+                    if state.error.lock().is_none() {
+                        if let Err(e) = job.run(&compactions) {
+                            *state.error.lock() = Some(e.to_string());
+                        }
+                    }
+                    let mut pending = state.pending.lock();
+                    *pending -= 1;
+                    if *pending == 0 {
+                        state.idle.notify_all();
+                    }
+                }
+            })
+        };
+        Flusher {
+            sender: Some(sender),
+            thread: Some(thread),
+            state,
+        }
+    }
+
+    pub(crate) fn schedule(&self, jobs: Vec<FlushJob>) {
+        *self.state.pending.lock() += jobs.len();
+        let sender = self.sender.as_ref().expect("flush thread is running");
+        for job in jobs {
+            // The thread only stops once the sender is dropped.
+            let _ = sender.send(job);
+        }
+    }
+
+    /// Fails if a flush has failed.
+    pub(crate) fn check(&self) -> Result<()> {
+        match &*self.state.error.lock() {
+            Some(e) => Err(ShortDBErrors::BackgroundError(e.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Blocks until every scheduled flush is done.
+    pub(crate) fn wait(&self) -> Result<()> {
+        let mut pending = self.state.pending.lock();
+        while *pending > 0 {
+            self.state.idle.wait(&mut pending);
+        }
+        drop(pending);
+        self.check()
+    }
+}
+
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use super::block::{unix_millis, Entry};
+use super::merge::{resolve, MergeOperator};
+use crate::errors::Result;
+use bytes::Bytes;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+pub(crate) type EntryIterator = Box<dyn DoubleEndedIterator<Item = Result<Entry>>>;
+
+/// Key bounds of a scan.
+pub(crate) type KeyRange = (Bound<Bytes>, Bound<Bytes>);
+
+pub(crate) fn key_range<K: AsRef<[u8]>, R: RangeBounds<K>>(range: R) -> KeyRange {
+    let to_bytes = |bound: Bound<&K>| match bound {
+        Bound::Included(k) => Bound::Included(Bytes::copy_from_slice(k.as_ref())),
+        Bound::Excluded(k) => Bound::Excluded(Bytes::copy_from_slice(k.as_ref())),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    (to_bytes(range.start_bound()), to_bytes(range.end_bound()))
+}
+
+/// The range of every key starting with `prefix`.
+pub(crate) fn prefix_range(prefix: &[u8]) -> KeyRange {
+    let start = Bound::Included(Bytes::copy_from_slice(prefix));
+    // The first key past the prefix: bump its last byte that can be bumped.
+    let end = match prefix.iter().rposition(|&b| b != u8::MAX) {
+        Some(i) => {
+            let mut end = prefix[..=i].to_vec();
+            end[i] += 1;
+            Bound::Excluded(Bytes::from(end))
+        }
+        None => Bound::Unbounded,
+    };
+    (start, end)
+}
+
+/// True if `[smallest, largest]` shares any key with `range`.
+pub(crate) fn overlaps_range(range: &KeyRange, smallest: &[u8], largest: &[u8]) -> bool {
+    let after_start = match &range.0 {
+        Bound::Included(start) => largest >= start.as_ref(),
+        Bound::Excluded(start) => largest > start.as_ref(),
+        Bound::Unbounded => true,
+    };
+    let before_end = match &range.1 {
+        Bound::Included(end) => smallest <= end.as_ref(),
+        Bound::Excluded(end) => smallest < end.as_ref(),
+        Bound::Unbounded => true,
+    };
+    after_start && before_end
+}
+
+/// Merges several sorted entry streams into one, from either end.
+///
+/// Entries come out ordered by key and newest version first within a key,
+/// so every version of every key is yielded. A version held by more than
+/// one source (the same key and sequence number) is yielded once.
+pub(crate) struct MergingIterator {
+    sources: Vec<EntryIterator>,
+    /// Entry each source holds for `next`, and for `next_back`.
+    fronts: Vec<Option<Entry>>,
+    backs: Vec<Option<Entry>>,
+    front_primed: bool,
+    back_primed: bool,
+}
+
+impl MergingIterator {
+    pub(crate) fn new(sources: Vec<EntryIterator>) -> Self {
+        let n = sources.len();
+        MergingIterator {
+            sources,
+            fronts: (0..n).map(|_| None).collect(),
+            backs: (0..n).map(|_| None).collect(),
+            front_primed: false,
+            back_primed: false,
+        }
+    }
+
+    fn advance_front(&mut self, i: usize) -> Result<()> {
+        self.fronts[i] = self.sources[i].next().transpose()?;
+        Ok(())
+    }
+
+    fn advance_back(&mut self, i: usize) -> Result<()> {
+        self.backs[i] = self.sources[i].next_back().transpose()?;
+        Ok(())
+    }
+
+    fn step(&mut self, forward: bool) -> Result<Option<Entry>> {
+        let primed = if forward {
+            &mut self.front_primed
+        } else {
+            &mut self.back_primed
+        };
+        if !*primed {
+            *primed = true;
+            for i in 0..self.sources.len() {
+                if forward {
+                    self.advance_front(i)?;
+                } else {
+                    self.advance_back(i)?;
+                }
+            }
+        }
+
+        // Once a source runs dry from one end, the entry held for the other
+        // end is the last one it has left.
+        for i in 0..self.sources.len() {
+            let (mine, theirs) = if forward {
+                (&mut self.fronts[i], &mut self.backs[i])
+            } else {
+                (&mut self.backs[i], &mut self.fronts[i])
+            };
+            if mine.is_none() {
+                *mine = theirs.take();
+            }
+        }
+
+        let heads = if forward { &self.fronts } else { &self.backs };
+        let mut winner: Option<usize> = None;
+        for (i, head) in heads.iter().enumerate() {
+            if let Some(entry) = head {
+                let better = match winner {
+                    None => true,
+                    Some(w) => {
+                        let order = entry.internal_cmp(heads[w].as_ref().unwrap());
+                        if forward {
+                            order.is_lt()
+                        } else {
+                            order.is_gt()
+                        }
+                    }
+                };
+                if better {
+                    winner = Some(i);
+                }
+            }
+        }
+        let Some(winner) = winner else {
+            return Ok(None);
+        };
+        let heads = if forward {
+            &mut self.fronts
+        } else {
+            &mut self.backs
+        };
+        let entry = heads[winner].take().unwrap();
+
+        // Step every source positioned on this version, the copies are duplicates.
+        for i in 0..self.sources.len() {
+            let heads = if forward { &self.fronts } else { &self.backs };
+            let on_key = i == winner
+                || heads[i]
+                    .as_ref()
+                    .is_some_and(|h| h.internal_cmp(&entry).is_eq());
+            if on_key {
+                if forward {
+                    self.advance_front(i)?;
+                } else {
+                    self.advance_back(i)?;
+                }
+            }
+        }
+        Ok(Some(entry))
+    }
+}
+
+impl Iterator for MergingIterator {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.step(true).transpose()
+    }
+}
+
+impl DoubleEndedIterator for MergingIterator {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.step(false).transpose()
+    }
+}
+
+/// Ordered scan over the whole database returned by
+/// [`crate::ShorterDB::iter`], [`crate::ShorterDB::range`] and
+/// [`crate::ShorterDB::scan_prefix`], or over a [`crate::Snapshot`].
+///
+/// Yields live `(key, value)` pairs in ascending key order, or descending
+/// order through [`DoubleEndedIterator`]: the newest version of every key
+/// visible to the scan wins and deleted or expired keys are skipped.
+pub struct DBIterator {
+    merged: MergingIterator,
+    /// Versions written after this sequence number are invisible.
+    sequence: u64,
+    /// Unix time in milliseconds the scan started at; puts that expired by
+    /// then are skipped.
+    now: u64,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// First version of the key after the last one handed out from each
+    /// end, read while looking for the end of that key.
+    front_peek: Option<Entry>,
+    back_peek: Option<Entry>,
+    done: bool,
+}
+
+impl DBIterator {
+    /// `sources` must be bounded to the scanned range.
+    pub(crate) fn new(
+        sources: Vec<EntryIterator>,
+        sequence: u64,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+    ) -> Self {
+        DBIterator {
+            merged: MergingIterator::new(sources),
+            sequence,
+            now: unix_millis(SystemTime::now()),
+            merge_operator,
+            front_peek: None,
+            back_peek: None,
+            done: false,
+        }
+    }
+
+    /// The next version from one end. Once the merge runs dry, the version
+    /// the other end read ahead is the last one left.
+    fn pull(&mut self, forward: bool) -> Result<Option<Entry>> {
+        let peeked = if forward {
+            self.front_peek.take()
+        } else {
+            self.back_peek.take()
+        };
+        if peeked.is_some() {
+            return Ok(peeked);
+        }
+        let next = if forward {
+            self.merged.next()
+        } else {
+            self.merged.next_back()
+        };
+        match next.transpose()? {
+            Some(entry) => Ok(Some(entry)),
+            None if forward => Ok(self.back_peek.take()),
+            None => Ok(self.front_peek.take()),
+        }
+    }
+
+    /// Consumes whole keys from one end until one has a live value.
+    fn next_key(&mut self, forward: bool) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        while let Some(first) = self.pull(forward)? {
+            let key = first.key.clone();
+            let mut visible = Vec::new();
+            let mut next = Some(first);
+            while let Some(entry) = next {
+                if entry.key != key {
+                    if forward {
+                        self.front_peek = Some(entry);
+                    } else {
+                        self.back_peek = Some(entry);
+                    }
+                    break;
+                }
+                if entry.seq <= self.sequence {
+                    visible.push(entry);
+                }
+                next = self.pull(forward)?;
+            }
+            // Forwards meets the versions of a key newest first, backwards
+            // oldest first.
+            if !forward {
+                visible.reverse();
+            }
+            let operator = self.merge_operator.as_deref();
+            let versions = visible.into_iter().map(Ok);
+            if let Some(value) = resolve(operator, &key, versions, self.now)? {
+                return Ok(Some((key.to_vec(), value)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn step(&mut self, forward: bool) -> Option<Result<(Vec<u8>, Vec<u8>)>> {
+        if self.done {
+            return None;
+        }
+        match self.next_key(forward) {
+            Ok(Some(pair)) => Some(Ok(pair)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl Iterator for DBIterator {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.step(true)
+    }
+}
+
+impl DoubleEndedIterator for DBIterator {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.step(false)
+    }
+}
@@ -0,0 +1,89 @@
+use crate::errors::{Result, ShortDBErrors};
+use parking_lot::{Condvar, Mutex};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Per-key exclusive locks held by pessimistic transactions.
+///
+/// A transaction that finds a key locked waits for it to be released, up to
+/// a timeout. While it waits, it has an edge in the wait-for graph pointing
+/// at the lock holder; a transaction only ever waits on one other, so the
+/// graph is a set of chains and a wait that would close a cycle is refused
+/// as a deadlock instead of being entered.
+#[derive(Default)]
+pub(crate) struct LockManager {
+    state: Mutex<LockState>,
+    released: Condvar,
+    next_txn: AtomicU64,
+}
+
+#[derive(Default)]
+struct LockState {
+    /// Key -> transaction holding it.
+    owners: HashMap<Vec<u8>, u64>,
+    /// Waiting transaction -> transaction holding the key it waits for.
+    waits_for: HashMap<u64, u64>,
+}
+
+impl LockState {
+    /// True if `from` is `to`, or waits on it through a chain of waits.
+    fn reaches(&self, mut from: u64, to: u64) -> bool {
+        for _ in 0..=self.waits_for.len() {
+            if from == to {
+                return true;
+            }
+            match self.waits_for.get(&from) {
+                Some(&next) => from = next,
+                None => return false,
+            }
+        }
+        false
+    }
+}
+
+impl LockManager {
+    /// A fresh transaction id.
+    pub(crate) fn begin(&self) -> u64 {
+        self.next_txn.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Locks `key` for `txn`, waiting at most `timeout` for its holder to
+    /// release it. Locking a key `txn` already holds succeeds at once.
+    pub(crate) fn lock(&self, txn: u64, key: &[u8], timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock();
+        let result = loop {
+            let owner = match state.owners.get(key) {
+                None => {
+                    state.owners.insert(key.to_vec(), txn);
+                    break Ok(());
+                }
+                Some(&owner) if owner == txn => break Ok(()),
+                Some(&owner) => owner,
+            };
+            if state.reaches(owner, txn) {
+                break Err(ShortDBErrors::Deadlock);
+            }
+            if Instant::now() >= deadline {
+                break Err(ShortDBErrors::LockTimeout);
+            }
+            state.waits_for.insert(txn, owner);
+            self.released.wait_until(&mut state, deadline);
+        };
+        state.waits_for.remove(&txn);
+        result
+    }
+
+    /// Releases every lock in `keys` held by `txn` and wakes the waiters.
+    pub(crate) fn unlock<'a>(&self, txn: u64, keys: impl IntoIterator<Item = &'a Vec<u8>>) {
+        let mut state = self.state.lock();
+        for key in keys {
+            if state.owners.get(key) == Some(&txn) {
+                state.owners.remove(key);
+            }
+        }
+        drop(state);
+        self.released.notify_all();
+    }
+}
@@ -0,0 +1,230 @@
+//! MANIFEST log of version edits.
+//!
+//! Every flush and compaction appends a [`VersionEdit`] describing the
+//! tables it added and removed. Replaying the log from the `MANIFEST-NNNNNN`
+//! file named in `CURRENT` gives back the exact set of live tables per
+//! level, so a table only becomes part of the database once its edit has
+//! been synced, and tables left behind by an interrupted flush or compaction
+//! are simply not live.
+//!
+//! Records are framed as `[crc32 u32][len u32][bincode encoded VersionEdit]`,
+//! with the checksum covering the length and the edit. A bad record at the
+//! end of the log, cut short or failing its checksum, is a write that never
+//! completed and is cut off; a bad record followed by intact ones means the
+//! log was damaged after the fact, and recovery fails.
+
+use crate::errors::{Result, ShortDBErrors};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const CURRENT_FILE: &str = "CURRENT";
+/// Once the log grows past this it is rewritten as a single snapshot edit.
+const MAX_MANIFEST_SIZE: u64 = 4 * 1024 * 1024;
+/// Checksum and length.
+const HEADER_SIZE: usize = 8;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct FileMeta {
+    pub(crate) level: usize,
+    pub(crate) number: u64,
+    pub(crate) size: u64,
+    pub(crate) smallest_key: Vec<u8>,
+    pub(crate) largest_key: Vec<u8>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct VersionEdit {
+    pub(crate) added: Vec<FileMeta>,
+    /// `(level, file number)` of every table removed.
+    pub(crate) deleted: Vec<(usize, u64)>,
+    pub(crate) next_file_number: Option<u64>,
+    pub(crate) last_sequence: Option<u64>,
+}
+
+/// The result of replaying a manifest.
+#[derive(Default)]
+pub(crate) struct VersionState {
+    pub(crate) levels: Vec<Vec<FileMeta>>,
+    pub(crate) next_file_number: u64,
+    pub(crate) last_sequence: u64,
+}
+
+impl VersionState {
+    pub(crate) fn apply(&mut self, edit: VersionEdit) {
+        for (level, number) in edit.deleted {
+            if let Some(files) = self.levels.get_mut(level) {
+                files.retain(|f| f.number != number);
+            }
+        }
+        for file in edit.added {
+            while self.levels.len() <= file.level {
+                self.levels.push(Vec::new());
+            }
+            self.levels[file.level].push(file);
+        }
+        if let Some(next) = edit.next_file_number {
+            self.next_file_number = self.next_file_number.max(next);
+        }
+        if let Some(seq) = edit.last_sequence {
+            self.last_sequence = self.last_sequence.max(seq);
+        }
+    }
+}
+
+pub(crate) struct Manifest {
+    dir: PathBuf,
+    number: u64,
+    file: File,
+    size: u64,
+}
+
+impl Manifest {
+    /// Replays the manifest named by `CURRENT`, or returns `None` if the
+    /// database has no manifest yet.
+    pub(crate) fn recover<P: AsRef<Path>>(dir: P) -> Result<Option<(Manifest, VersionState)>> {
+        let dir = dir.as_ref().to_path_buf();
+        let current = match fs::read_to_string(dir.join(CURRENT_FILE)) {
+            Ok(current) => current,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let name = current.trim();
+        let number = name
+            .strip_prefix("MANIFEST-")
+            .and_then(|n| n.parse::<u64>().ok())
+            .ok_or_else(|| ShortDBErrors::CorruptedManifest(format!("bad CURRENT: {:?}", name)))?;
+
+        let path = dir.join(name);
+        let mut data = Vec::new();
+        File::open(&path)?.read_to_end(&mut data)?;
+
+        let mut state = VersionState {
+            next_file_number: 1,
+            ..Default::default()
+        };
+        let mut pos = 0;
+        while pos < data.len() {
+            let end = match record_end(&data, pos) {
+                Some(end) => end,
+                // Only the last append can be torn by a crash.
+                None if (pos + 1..data.len()).any(|start| record_end(&data, start).is_some()) => {
+                    return Err(ShortDBErrors::CorruptedManifest(format!(
+                        "{}: bad record at offset {}",
+                        name, pos
+                    )))
+                }
+                None => break,
+            };
+            let edit: VersionEdit = bincode::deserialize(&data[pos + HEADER_SIZE..end])
+                .map_err(|e| ShortDBErrors::CorruptedManifest(format!("{}: {}", name, e)))?;
+            state.apply(edit);
+            pos = end;
+        }
+
+        // Drop the torn tail, if any, so new edits follow the last good one.
+        let file = OpenOptions::new().append(true).open(&path)?;
+        if pos < data.len() {
+            file.set_len(pos as u64)?;
+            file.sync_all()?;
+        }
+        let manifest = Manifest {
+            dir,
+            number,
+            file,
+            size: pos as u64,
+        };
+        Ok(Some((manifest, state)))
+    }
+
+    /// Starts a new manifest holding `snapshot` and points `CURRENT` at it.
+    pub(crate) fn create<P: AsRef<Path>>(
+        dir: P,
+        number: u64,
+        snapshot: &VersionEdit,
+    ) -> Result<Manifest> {
+        let dir = dir.as_ref().to_path_buf();
+        let name = manifest_name(number);
+        let record = encode(snapshot)?;
+        write_atomically(&dir, &name, &record)?;
+        write_atomically(&dir, CURRENT_FILE, format!("{}\n", name).as_bytes())?;
+
+        let file = OpenOptions::new().append(true).open(dir.join(&name))?;
+        Ok(Manifest {
+            dir,
+            number,
+            file,
+            size: record.len() as u64,
+        })
+    }
+
+    /// Appends `edit` and syncs it. Once this returns the edit is durable.
+    pub(crate) fn log_edit(&mut self, edit: &VersionEdit) -> Result<()> {
+        let record = encode(edit)?;
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+        self.size += record.len() as u64;
+        Ok(())
+    }
+
+    pub(crate) fn needs_rotation(&self) -> bool {
+        self.size > MAX_MANIFEST_SIZE
+    }
+
+    /// Replaces this manifest with a new one holding only `snapshot`.
+    pub(crate) fn rotate(&mut self, snapshot: &VersionEdit) -> Result<()> {
+        let next = Manifest::create(&self.dir, self.number + 1, snapshot)?;
+        let old = std::mem::replace(self, next);
+        fs::remove_file(old.dir.join(manifest_name(old.number)))?;
+        Ok(())
+    }
+}
+
+fn manifest_name(number: u64) -> String {
+    format!("MANIFEST-{:06}", number)
+}
+
+fn encode(edit: &VersionEdit) -> Result<Vec<u8>> {
+    let body = bincode::serialize(edit)
+        .map_err(|e| ShortDBErrors::CorruptedManifest(format!("could not encode edit: {}", e)))?;
+    let mut record = Vec::with_capacity(HEADER_SIZE + body.len());
+    record.extend_from_slice(&[0; 4]);
+    record.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    record.extend_from_slice(&body);
+    let crc = crc32fast::hash(&record[4..]);
+    record[..4].copy_from_slice(&crc.to_le_bytes());
+    Ok(record)
+}
+
+/// Where the record starting at `pos` ends, if it is whole and passes its
+/// checksum.
+fn record_end(data: &[u8], pos: usize) -> Option<usize> {
+    if data.len() - pos < HEADER_SIZE {
+        return None;
+    }
+    let crc = u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+    let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+    let end = pos.checked_add(HEADER_SIZE + len)?;
+    if end > data.len() || crc32fast::hash(&data[pos + 4..end]) != crc {
+        return None;
+    }
+    Some(end)
+}
+
+/// Writes `name` through a uniquely named temporary file and a rename, so
+/// readers only ever see the old or the complete new contents.
+pub(crate) fn write_atomically(dir: &Path, name: &str, contents: &[u8]) -> io::Result<()> {
+    static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+    let tmp = dir.join(format!(
+        "{}.{}-{}.tmp",
+        name,
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, dir.join(name))
+}
@@ -1,67 +1,116 @@
+use super::block::Entry;
+use super::iterator::KeyRange;
+use super::wal::WALEntry;
 use crate::errors::{Result, ShortDBErrors};
 use bytes::Bytes;
 use crossbeam_skiplist::SkipMap;
+use std::cmp::Ordering;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 use std::sync::Arc;
 
-#[derive(Clone)]
+/// Skiplist key of one version: ascending by key, then newest first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct InternalKey {
+    key: Bytes,
+    seq: u64,
+}
+
+impl Ord for InternalKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key
+            .cmp(&other.key)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for InternalKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 pub(crate) struct Memtable {
-    pub(crate) memtable: Arc<SkipMap<Bytes, Bytes>>,
-    pub(crate) size: u64,
+    /// Every version written since the last flush.
+    pub(crate) memtable: Arc<SkipMap<InternalKey, Entry>>,
+    /// Counted atomically so that writes only need a shared reference.
+    pub(crate) size: AtomicU64,
 }
 
 impl Memtable {
     pub(crate) fn new() -> Self {
         Memtable {
             memtable: Arc::new(SkipMap::new()),
-            size: 0,
+            size: AtomicU64::new(0),
         }
     }
 
-    pub(crate) fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
-        match self.memtable.get(key).map(|e| e.value().clone()) {
-            Some(v) if v == Bytes::copy_from_slice(b"tombstone") => Ok(None),
-            Some(v) => Ok(Some(v)),
-            None => Err(ShortDBErrors::KeyNotFound),
+    /// The newest version of `key` no newer than `seq`, tombstones included.
+    pub(crate) fn get_entry(&self, key: &[u8], seq: u64) -> Option<Entry> {
+        let target = InternalKey {
+            key: Bytes::copy_from_slice(key),
+            seq,
+        };
+        let found = self.memtable.lower_bound(Bound::Included(&target))?;
+        if found.key().key != key {
+            return None;
         }
+        Some(found.value().clone())
     }
 
-    pub(crate) fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
-        // Insert the key-value pair into the memtable
-        self.memtable
-            .insert(Bytes::copy_from_slice(&key), Bytes::copy_from_slice(value));
-        // let mut size = *self.size.lock().unwrap();
-        self.size += 1;
-        dbg!(self.size);
-        // Check if the insertion was successful
-        if self.memtable.get(key).is_some() {
-            if self.size >= 256 {
-                return Err(ShortDBErrors::FlushNeededFromMemTable); // Indicate that a flush is needed
-            }
-            Ok(())
-        } else {
-            Err(ShortDBErrors::ValueNotSet) // Use a meaningful error
-        }
-    }
-    pub(crate) fn delete(&mut self, key: &[u8]) -> Result<()> {
-        //when we say we delete a key, we set its value to tombstone
+    /// Inserts `entry` as the version of its key with sequence number `seq`.
+    /// A delete inserts a tombstone version.
+    pub(crate) fn add(&self, seq: u64, entry: &WALEntry) -> Result<()> {
         self.memtable.insert(
-            Bytes::copy_from_slice(key),
-            Bytes::copy_from_slice(b"tombstone"),
+            InternalKey {
+                key: entry.key.clone(),
+                seq,
+            },
+            Entry {
+                key: entry.key.clone(),
+                seq,
+                kind: entry.kind,
+                expires_at: entry.expires_at,
+                value: entry.value.clone(),
+            },
         );
-
-        // let mut size = *self.size.lock().unwrap();
-        self.size += 1;
-
-        // Check if the insertion was successful
-        if self.size >= 256 {
+        let size = self.size.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+        if size >= 256 {
             return Err(ShortDBErrors::FlushNeededFromMemTable); // Indicate that a flush is needed
         }
-
         Ok(())
     }
-    pub(crate) fn clear(&mut self) {
-        self.memtable.clear();
-        // *self.size.lock().unwrap() = 0;
-        self.size = 0;
+
+    /// Copies out every version within `range`, tombstones included. The
+    /// memtable is small enough that iterators can take this snapshot
+    /// instead of borrowing the skiplist.
+    pub(crate) fn entries(&self, range: &KeyRange) -> Vec<Entry> {
+        // Versions of a key run from seq u64::MAX down to 0.
+        let start = match &range.0 {
+            Bound::Included(key) => Bound::Included(InternalKey {
+                key: key.clone(),
+                seq: u64::MAX,
+            }),
+            Bound::Excluded(key) => Bound::Excluded(InternalKey {
+                key: key.clone(),
+                seq: 0,
+            }),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let end = match &range.1 {
+            Bound::Included(key) => Bound::Included(InternalKey {
+                key: key.clone(),
+                seq: 0,
+            }),
+            Bound::Excluded(key) => Bound::Excluded(InternalKey {
+                key: key.clone(),
+                seq: u64::MAX,
+            }),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        self.memtable
+            .range((start, end))
+            .map(|e| e.value().clone())
+            .collect()
     }
 }
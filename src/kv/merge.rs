@@ -0,0 +1,194 @@
+//! Merge operators.
+//!
+//! [`ShorterDB::merge`](crate::ShorterDB::merge) records an operand for a key
+//! without reading it. A read finds the newest put or delete of the key, its
+//! base, and folds every operand written after it onto the base with the
+//! configured [`MergeOperator`]. Compaction folds operands ahead of time, so
+//! they do not pile up.
+
+use super::block::{Entry, ValueKind};
+use crate::errors::{Result, ShortDBErrors};
+use bytes::Bytes;
+use std::fmt;
+
+/// Combines merge operands with the value of a key.
+///
+/// Registered through [`crate::Options::merge_operator`]. An operator must
+/// give the same result however compaction groups the operands, so
+/// `partial_merge` has to be associative with `full_merge`.
+pub trait MergeOperator: Send + Sync {
+    /// Identifies the operator in errors.
+    fn name(&self) -> &str;
+
+    /// Folds `operands`, oldest first, onto `existing`, the value of the key
+    /// before them, if it had one. Returns `None` if the operands or the
+    /// value cannot be merged.
+    fn full_merge(
+        &self,
+        key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Option<Vec<u8>>;
+
+    /// Combines two consecutive operands, `left` being the older one, into
+    /// one with the same effect. Returns `None` when that is not possible;
+    /// both operands are then kept as they are.
+    fn partial_merge(&self, _key: &[u8], _left: &[u8], _right: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+impl fmt::Debug for dyn MergeOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MergeOperator({})", self.name())
+    }
+}
+
+/// Adds operands to a counter. The value and every operand are `u64`s in
+/// little endian byte order; a missing value counts as 0 and the sum wraps.
+pub struct U64AddOperator;
+
+fn read_u64(bytes: &[u8]) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+impl MergeOperator for U64AddOperator {
+    fn name(&self) -> &str {
+        "u64_add"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Option<Vec<u8>> {
+        let mut sum = existing.map_or(Some(0), read_u64)?;
+        for operand in operands {
+            sum = sum.wrapping_add(read_u64(operand)?);
+        }
+        Some(sum.to_le_bytes().to_vec())
+    }
+
+    fn partial_merge(&self, _key: &[u8], left: &[u8], right: &[u8]) -> Option<Vec<u8>> {
+        let sum = read_u64(left)?.wrapping_add(read_u64(right)?);
+        Some(sum.to_le_bytes().to_vec())
+    }
+}
+
+/// Appends operands to the value, separated by a delimiter.
+pub struct AppendOperator {
+    delimiter: Vec<u8>,
+}
+
+impl AppendOperator {
+    /// Appends with nothing between the pieces.
+    pub fn new() -> Self {
+        Self::with_delimiter(b"")
+    }
+
+    pub fn with_delimiter(delimiter: &[u8]) -> Self {
+        AppendOperator {
+            delimiter: delimiter.to_vec(),
+        }
+    }
+}
+
+impl Default for AppendOperator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MergeOperator for AppendOperator {
+    fn name(&self) -> &str {
+        "append"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Option<Vec<u8>> {
+        let mut pieces = existing.into_iter().chain(operands.iter().copied());
+        let mut value = pieces.next().unwrap_or_default().to_vec();
+        for piece in pieces {
+            value.extend_from_slice(&self.delimiter);
+            value.extend_from_slice(piece);
+        }
+        Some(value)
+    }
+
+    fn partial_merge(&self, key: &[u8], left: &[u8], right: &[u8]) -> Option<Vec<u8>> {
+        self.full_merge(key, Some(left), &[right])
+    }
+}
+
+/// Keeps the largest of the value and the operands, comparing bytes
+/// lexicographically. Store numbers big endian for this to order them.
+pub struct MaxOperator;
+
+impl MergeOperator for MaxOperator {
+    fn name(&self) -> &str {
+        "max"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Option<Vec<u8>> {
+        let max = existing.into_iter().chain(operands.iter().copied()).max()?;
+        Some(max.to_vec())
+    }
+
+    fn partial_merge(&self, _key: &[u8], left: &[u8], right: &[u8]) -> Option<Vec<u8>> {
+        Some(left.max(right).to_vec())
+    }
+}
+
+/// Computes the value of `key` from its visible versions, newest first:
+/// merge operands are collected up to the first put or delete, which is the
+/// base they are folded onto. A put that expired by `now` is no base.
+pub(crate) fn resolve(
+    operator: Option<&dyn MergeOperator>,
+    key: &[u8],
+    versions: impl IntoIterator<Item = Result<Entry>>,
+    now: u64,
+) -> Result<Option<Vec<u8>>> {
+    let mut operands = Vec::new();
+    let mut base = None;
+    for version in versions {
+        let version = version?;
+        match version.kind {
+            ValueKind::Merge => operands.push(version.value),
+            ValueKind::Put => {
+                if !version.is_expired(now) {
+                    base = Some(version.value);
+                }
+                break;
+            }
+            ValueKind::Delete => break,
+        }
+    }
+    if operands.is_empty() {
+        return Ok(base.map(|b| b.to_vec()));
+    }
+    full_merge(operator, key, base.as_deref(), &operands).map(Some)
+}
+
+/// Runs `operator` over `operands`, given newest first.
+pub(crate) fn full_merge(
+    operator: Option<&dyn MergeOperator>,
+    key: &[u8],
+    base: Option<&[u8]>,
+    operands: &[Bytes],
+) -> Result<Vec<u8>> {
+    let operator = operator.ok_or(ShortDBErrors::NoMergeOperator)?;
+    let operands: Vec<&[u8]> = operands.iter().rev().map(|o| o.as_ref()).collect();
+    operator
+        .full_merge(key, base, &operands)
+        .ok_or_else(|| ShortDBErrors::MergeFailed(operator.name().to_string()))
+}
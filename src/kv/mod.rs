@@ -1,5 +1,22 @@
+pub mod batch;
+pub(crate) mod block;
+pub mod cache;
+pub mod column_family;
+pub(crate) mod commit;
+pub(crate) mod compaction;
+pub(crate) mod compression;
 pub mod db;
+pub(crate) mod flush;
+pub(crate) mod iterator;
+pub(crate) mod lock;
+pub(crate) mod manifest;
 pub(crate) mod memtable;
+pub mod merge;
+pub mod options;
+pub(crate) mod scheduler;
+pub mod snapshot;
 pub(crate) mod sst;
-pub(crate) mod utils;
+pub mod stall;
+pub(crate) mod table;
+pub mod transaction;
 pub(crate) mod wal;
@@ -0,0 +1,215 @@
+use super::cache::BlockCache;
+use super::compaction::L0_COMPACTION_TRIGGER;
+use super::compression::{Compressor, FIRST_CUSTOM_ID};
+use super::manifest::write_atomically;
+use super::merge::MergeOperator;
+use crate::errors::{Result, ShortDBErrors};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+const OPTIONS_FILE: &str = "OPTIONS";
+
+/// How SST files are reorganised as they accumulate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompactionStrategy {
+    /// Levels of non-overlapping tables, each ten times bigger than the last.
+    /// Cheapest reads, but data is rewritten once per level.
+    #[default]
+    Leveled,
+    /// Size-tiered sorted runs that are merged once enough runs of similar
+    /// size pile up. Far fewer rewrites at the cost of reading more runs.
+    Universal,
+}
+
+impl CompactionStrategy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CompactionStrategy::Leveled => "leveled",
+            CompactionStrategy::Universal => "universal",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "leveled" => Some(CompactionStrategy::Leveled),
+            "universal" => Some(CompactionStrategy::Universal),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CompactionStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Codec applied to SST data blocks as they are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompressionType {
+    #[default]
+    None,
+    /// DEFLATE through `flate2`.
+    Deflate,
+    /// Requires the `lz4` cargo feature.
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// Requires the `zstd` cargo feature.
+    #[cfg(feature = "zstd")]
+    Zstd,
+    /// Requires the `snappy` cargo feature.
+    #[cfg(feature = "snappy")]
+    Snappy,
+    /// The codec with this id in [`Options::compressors`].
+    Custom(u8),
+}
+
+/// Per-database options given to [`crate::ShorterDB::new_with_options`].
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Fixed when the database is created and recorded in its `OPTIONS` file.
+    pub compaction_strategy: CompactionStrategy,
+    /// Can change between opens: every block records its own codec, so
+    /// tables written with another codec stay readable.
+    pub compression: CompressionType,
+    /// Map every SST file into memory when it is opened and serve block
+    /// reads from the mapping instead of a `pread` per block. Falls back to
+    /// `pread` for any file that cannot be mapped.
+    pub mmap_reads: bool,
+    /// Cache for decoded data blocks. Pass clones of the same `Arc` to
+    /// several databases to bound their memory together.
+    pub block_cache: Option<Arc<BlockCache>>,
+    /// Folds the operands written by [`crate::ShorterDB::merge`]. Reading a
+    /// key that has operands without one fails with `NoMergeOperator`.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// Codecs beyond the built-in ones, for [`CompressionType::Custom`].
+    /// Each needs an id of its own from 128 to 255, and must stay
+    /// registered while any table holds blocks it compressed.
+    pub compressors: Vec<Arc<dyn Compressor>>,
+    /// Threads running compactions in the background, shared by every
+    /// column family; only the options the database is opened with count.
+    /// Compactions that touch no common table run side by side.
+    pub max_background_compactions: usize,
+    /// Writes stop while a column family has this many full Memtables
+    /// waiting to be flushed, and are slowed down at one fewer. Must be at
+    /// least 1.
+    pub max_immutable_memtables: usize,
+    /// Writes are slowed down while a column family has this many level 0
+    /// tables. Level 0 is only compacted from 4 tables on, so both level 0
+    /// triggers need to be at least that, or opening fails with
+    /// `InvalidOptions`.
+    pub level0_slowdown_writes_trigger: usize,
+    /// Writes stop while a column family has this many level 0 tables.
+    /// Must be at least the slowdown trigger.
+    pub level0_stop_writes_trigger: usize,
+    /// How long a stopped write waits before failing with `WriteStall`.
+    /// Only the options the database is opened with count.
+    pub write_stall_timeout: Duration,
+    /// Sync the WAL before a write returns, so that it survives a power
+    /// failure and not just the process crashing. Writers that arrive
+    /// together share one sync. Only the options the database is opened
+    /// with count.
+    pub sync_writes: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            compaction_strategy: CompactionStrategy::default(),
+            compression: CompressionType::default(),
+            mmap_reads: false,
+            block_cache: None,
+            merge_operator: None,
+            compressors: Vec::new(),
+            max_background_compactions: 2,
+            max_immutable_memtables: 4,
+            level0_slowdown_writes_trigger: 8,
+            level0_stop_writes_trigger: 12,
+            write_stall_timeout: Duration::from_secs(10),
+            sync_writes: false,
+        }
+    }
+}
+
+impl Options {
+    /// Rejects options that contradict each other.
+    pub(crate) fn validate(&self) -> Result<()> {
+        let invalid = |reason: String| Err(ShortDBErrors::InvalidOptions(reason));
+        for (i, codec) in self.compressors.iter().enumerate() {
+            if codec.id() < FIRST_CUSTOM_ID {
+                return invalid(format!(
+                    "codec {} has id {}, but ids below {} are reserved",
+                    codec.name(),
+                    codec.id(),
+                    FIRST_CUSTOM_ID
+                ));
+            }
+            if self.compressors[..i].iter().any(|c| c.id() == codec.id()) {
+                return invalid(format!("two codecs have id {}", codec.id()));
+            }
+        }
+        if let CompressionType::Custom(id) = self.compression {
+            if !self.compressors.iter().any(|c| c.id() == id) {
+                return invalid(format!("no codec with id {} is registered", id));
+            }
+        }
+        if self.max_immutable_memtables == 0 {
+            return invalid("max_immutable_memtables must be at least 1".to_string());
+        }
+        if self.level0_slowdown_writes_trigger < L0_COMPACTION_TRIGGER {
+            return invalid(format!(
+                "level0_slowdown_writes_trigger is {}, but level 0 is only compacted from {} tables on",
+                self.level0_slowdown_writes_trigger, L0_COMPACTION_TRIGGER
+            ));
+        }
+        if self.level0_stop_writes_trigger < self.level0_slowdown_writes_trigger {
+            return invalid(format!(
+                "level0_stop_writes_trigger ({}) is below level0_slowdown_writes_trigger ({})",
+                self.level0_stop_writes_trigger, self.level0_slowdown_writes_trigger
+            ));
+        }
+        Ok(())
+    }
+
+    /// Records these options in a new database directory, or checks them
+    /// against the ones the existing database was created with.
+    pub(crate) fn check_or_persist<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let dir = dir.as_ref();
+        let contents = match fs::read_to_string(dir.join(OPTIONS_FILE)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let contents = format!("compaction_strategy={}\n", self.compaction_strategy);
+                write_atomically(dir, OPTIONS_FILE, contents.as_bytes())?;
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut on_disk = None;
+        for line in contents.lines() {
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| ShortDBErrors::InvalidOptionsFile(line.to_string()))?;
+            if name == "compaction_strategy" {
+                on_disk = Some(
+                    CompactionStrategy::parse(value)
+                        .ok_or_else(|| ShortDBErrors::InvalidOptionsFile(line.to_string()))?,
+                );
+            }
+        }
+        let on_disk = on_disk.ok_or_else(|| {
+            ShortDBErrors::InvalidOptionsFile("no compaction_strategy line".to_string())
+        })?;
+        if on_disk != self.compaction_strategy {
+            return Err(ShortDBErrors::CompactionStrategyMismatch {
+                on_disk,
+                requested: self.compaction_strategy,
+            });
+        }
+        Ok(())
+    }
+}
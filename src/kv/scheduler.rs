@@ -0,0 +1,207 @@
+//! Background compactions.
+//!
+//! A pool of worker threads, [`Options::max_background_compactions`] of
+//! them, takes turns on a queue of column families that may need
+//! compacting. A family joins the queue whenever a flush adds a table to it.
+//! A worker picks the most needed compaction of the family, scoring levels
+//! by size against their budget and level 0 by table count as well, and
+//! puts the family straight back in the queue, so that another worker can
+//! pick a compaction sharing no table with it and run alongside. Only
+//! picking and installing take the family lock; the merge runs without it.
+//!
+//! Compactions can be paused, so that no new one starts, and canceled,
+//! which also makes the running ones stop and throw away what they wrote.
+//!
+//! [`Options::max_background_compactions`]: super::options::Options::max_background_compactions
+
+use super::column_family::ColumnFamily;
+use crate::errors::{Result, ShortDBErrors};
+use parking_lot::{Condvar, Mutex, RwLock};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+#[derive(Default)]
+struct State {
+    /// Families that may need compacting, each at most once.
+    queue: VecDeque<Arc<RwLock<ColumnFamily>>>,
+    /// Compactions being picked, run or installed.
+    running: usize,
+    paused: bool,
+    shutdown: bool,
+    /// What made the first failed compaction fail.
+    error: Option<String>,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled when there is work to pick up, or the pool shuts down.
+    work: Condvar,
+    /// Signalled when a compaction finishes.
+    finished: Condvar,
+    /// Bumped to make running compactions stop.
+    cancel_epoch: AtomicU64,
+}
+
+impl Shared {
+    fn worker(&self) {
+        loop {
+            let family = {
+                let mut state = self.state.lock();
+                loop {
+                    if state.shutdown {
+                        return;
+                    }
+                    if !state.paused && state.error.is_none() {
+                        if let Some(family) = state.queue.pop_front() {
+                            state.running += 1;
+                            break family;
+                        }
+                    }
+                    self.work.wait(&mut state);
+                }
+            };
+            let result = self.compact(&family);
+
+            let mut state = self.state.lock();
+            state.running -= 1;
+            match result {
+                // Its tree changed, so it may need more; the picks other
+                // workers passed on may be free now as well.
+                Ok(true) => {
+                    Self::enqueue(&mut state, family);
+                    self.work.notify_one();
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    state.error.get_or_insert(e.to_string());
+                }
+            }
+            self.finished.notify_all();
+        }
+    }
+
+    /// Runs one compaction of `family`, if it needs one that is free.
+    fn compact(&self, family: &Arc<RwLock<ColumnFamily>>) -> Result<bool> {
+        let epoch = self.cancel_epoch.load(Ordering::Acquire);
+        let compaction = {
+            let mut family = family.write();
+            if family.dropped {
+                return Ok(false);
+            }
+            match family.sst.pick_compaction()? {
+                Some(compaction) => compaction,
+                None => return Ok(false),
+            }
+        };
+        {
+            let mut state = self.state.lock();
+            Self::enqueue(&mut state, Arc::clone(family));
+            self.work.notify_one();
+        }
+
+        let outputs = compaction.run(&|| self.cancel_epoch.load(Ordering::Acquire) != epoch);
+        let mut family = family.write();
+        if family.dropped {
+            return Ok(false);
+        }
+        match outputs {
+            Ok(outputs) => {
+                let installed = outputs.is_some();
+                family.sst.finish_compaction(&compaction, outputs)?;
+                Ok(installed)
+            }
+            Err(e) => {
+                family.sst.finish_compaction(&compaction, None)?;
+                Err(e)
+            }
+        }
+    }
+
+    fn enqueue(state: &mut State, family: Arc<RwLock<ColumnFamily>>) {
+        if !state.queue.iter().any(|f| Arc::ptr_eq(f, &family)) {
+            state.queue.push_back(family);
+        }
+    }
+}
+
+/// Handle on the compaction workers. Dropping it cancels the running
+/// compactions and waits for the workers to stop.
+pub(crate) struct CompactionScheduler {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl CompactionScheduler {
+    /// Starts `threads` workers, at least one.
+    pub(crate) fn start(threads: usize) -> Self {
+        let shared = Arc::new(Shared::default());
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || shared.worker())
+            })
+            .collect();
+        CompactionScheduler { shared, workers }
+    }
+
+    /// Queues `family` to be checked for compactions it needs.
+    pub(crate) fn schedule(&self, family: &Arc<RwLock<ColumnFamily>>) {
+        let mut state = self.shared.state.lock();
+        Shared::enqueue(&mut state, Arc::clone(family));
+        self.shared.work.notify_one();
+    }
+
+    /// Fails if a compaction has failed.
+    pub(crate) fn check(&self) -> Result<()> {
+        match &self.shared.state.lock().error {
+            Some(e) => Err(ShortDBErrors::BackgroundError(e.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Blocks until no compaction runs and, unless paused, none is needed.
+    pub(crate) fn wait(&self) -> Result<()> {
+        let mut state = self.shared.state.lock();
+        while state.running > 0
+            || (!state.queue.is_empty() && !state.paused && state.error.is_none())
+        {
+            self.shared.finished.wait(&mut state);
+        }
+        drop(state);
+        self.check()
+    }
+
+    pub(crate) fn pause(&self) {
+        self.shared.state.lock().paused = true;
+    }
+
+    pub(crate) fn resume(&self) {
+        self.shared.state.lock().paused = false;
+        self.shared.work.notify_all();
+    }
+
+    /// Pauses and makes the running compactions stop, waiting until they
+    /// have.
+    pub(crate) fn cancel(&self) {
+        let mut state = self.shared.state.lock();
+        state.paused = true;
+        self.shared.cancel_epoch.fetch_add(1, Ordering::AcqRel);
+        while state.running > 0 {
+            self.shared.finished.wait(&mut state);
+        }
+    }
+}
+
+impl Drop for CompactionScheduler {
+    fn drop(&mut self) {
+        self.shared.state.lock().shutdown = true;
+        self.shared.cancel_epoch.fetch_add(1, Ordering::AcqRel);
+        self.shared.work.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
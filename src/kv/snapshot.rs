@@ -0,0 +1,113 @@
+use super::db::ShorterDB;
+use super::iterator::{key_range, prefix_range, DBIterator};
+use crate::errors::Result;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::ops::RangeBounds;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Sequence numbers of the live snapshots of one database, each with the
+/// number of handles holding it. Compaction keeps every version one of
+/// them can still see.
+#[derive(Clone, Default)]
+pub(crate) struct SnapshotList {
+    live: Arc<Mutex<BTreeMap<u64, usize>>>,
+}
+
+impl SnapshotList {
+    pub(crate) fn acquire(&self, seq: u64) {
+        *self.live.lock().entry(seq).or_insert(0) += 1;
+    }
+
+    /// Acquires the newest published sequence number. It is read with the
+    /// list locked, so a flush or compaction that already looked at the
+    /// list only ever dropped versions older than what this returns.
+    fn acquire_latest(&self, last_sequence: &AtomicU64) -> u64 {
+        let mut live = self.live.lock();
+        let seq = last_sequence.load(Ordering::Acquire);
+        *live.entry(seq).or_insert(0) += 1;
+        seq
+    }
+
+    fn release(&self, seq: u64) {
+        let mut live = self.live.lock();
+        if let Some(count) = live.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                live.remove(&seq);
+            }
+        }
+    }
+
+    /// Live snapshot sequence numbers, in ascending order.
+    pub(crate) fn sequences(&self) -> Vec<u64> {
+        self.live.lock().keys().copied().collect()
+    }
+}
+
+/// A consistent, read-only view of a database as of the moment
+/// [`ShorterDB::snapshot`] was called.
+///
+/// Reads through a snapshot ignore every write made after it was taken,
+/// including flushes and compactions that rewrite the data underneath it.
+/// The view is held until the handle, and every clone of it, is dropped.
+pub struct Snapshot {
+    seq: u64,
+    list: SnapshotList,
+}
+
+impl Snapshot {
+    pub(crate) fn new(seq: u64, list: SnapshotList) -> Self {
+        list.acquire(seq);
+        Snapshot { seq, list }
+    }
+
+    /// A snapshot of the newest write published in `last_sequence`.
+    pub(crate) fn latest(last_sequence: &AtomicU64, list: SnapshotList) -> Self {
+        let seq = list.acquire_latest(last_sequence);
+        Snapshot { seq, list }
+    }
+
+    /// Sequence number of the last write visible to this snapshot.
+    pub fn sequence(&self) -> u64 {
+        self.seq
+    }
+
+    /// Like [`ShorterDB::get`], as of this snapshot.
+    pub fn get(&self, db: &ShorterDB, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        db.get_at(key, self.seq)
+    }
+
+    /// Like [`ShorterDB::get_cf`], as of this snapshot.
+    pub fn get_cf(&self, db: &ShorterDB, cf: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        db.families.read().named(cf)?.read().get_at(key, self.seq)
+    }
+
+    /// Like [`ShorterDB::iter`], as of this snapshot.
+    pub fn iter(&self, db: &ShorterDB) -> DBIterator {
+        self.range::<&[u8], _>(db, ..)
+    }
+
+    /// Like [`ShorterDB::range`], as of this snapshot.
+    pub fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, db: &ShorterDB, range: R) -> DBIterator {
+        db.scan_at(key_range(range), self.seq)
+    }
+
+    /// Like [`ShorterDB::scan_prefix`], as of this snapshot.
+    pub fn scan_prefix(&self, db: &ShorterDB, prefix: &[u8]) -> DBIterator {
+        db.scan_at(prefix_range(prefix), self.seq)
+    }
+}
+
+impl Clone for Snapshot {
+    fn clone(&self) -> Self {
+        Snapshot::new(self.seq, self.list.clone())
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.list.release(self.seq);
+    }
+}
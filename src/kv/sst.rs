@@ -1,185 +1,390 @@
 use std::{
-    collections::VecDeque,
-    fs::{self, create_dir, remove_file, OpenOptions},
-    io::Write,
-    path::{self, Path, PathBuf},
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{self, create_dir_all},
+    ops::Bound,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
-use std::fs::File;
-use std::io::{BufReader, Read};
+use bytes::Bytes;
 
-// use anyhow::Error;
+use crate::errors::{Result, ShortDBErrors};
 
-use anyhow::Error;
+use super::{
+    block::Entry,
+    compaction::{Retention, MAX_LEVELS},
+    iterator::{overlaps_range, EntryIterator, KeyRange},
+    manifest::{FileMeta, Manifest, VersionEdit, VersionState},
+    memtable::Memtable,
+    options::{CompressionType, Options},
+    snapshot::SnapshotList,
+    table::{Table, TableBuilder, TableIterator, TableOptions},
+};
 
-use super::{memtable::Memtable, utils::bytes_to_string};
+/// Size budget of levels 0 and 1; every deeper level gets ten times its parent.
+const BASE_LEVEL_SIZE: usize = 1024 * 1024;
 
+#[allow(clippy::upper_case_acronyms)]
 pub(crate) struct SST {
     pub(crate) dir: PathBuf,
     pub(crate) levels: Vec<PathBuf>,
+    /// Open tables per level. Level 0 is ordered newest first and may
+    /// overlap, deeper levels are ordered by key and never overlap.
+    pub(crate) tables: Vec<Vec<Arc<Table>>>,
     pub(crate) max_level_size: Vec<usize>,
     pub(crate) curr_level_size: Vec<usize>,
-    pub(crate) queue: VecDeque<Memtable>,
+    /// Full Memtables waiting for the flush thread, oldest first, each with
+    /// the last sequence number logged before it was frozen. They stay
+    /// readable until their table is installed.
+    pub(crate) queue: VecDeque<(u64, Arc<Memtable>)>,
+    /// Shared with running compactions, which take numbers for their
+    /// output without holding the SST.
+    pub(crate) next_file_number: Arc<AtomicU64>,
+    /// Newest write, of any column family, logged before the last flush:
+    /// the WAL holds nothing older that this family still needs.
+    pub(crate) last_sequence: u64,
+    /// Snapshots whose versions flushes and compactions must keep.
+    pub(crate) snapshots: SnapshotList,
+    /// File numbers of the tables running compactions are merging.
+    pub(crate) compacting: HashSet<u64>,
+    /// Largest key of the last table compacted out of each level.
+    pub(crate) compact_pointer: Vec<Option<Bytes>>,
+    pub(crate) manifest: Manifest,
+    pub(crate) options: Options,
+    pub(crate) table_options: TableOptions,
     // parralellisation: todo!(),
 }
 
 impl SST {
-    pub(crate) fn open(db_name: String) -> Self {
-        let dir;
-        match create_dir("./".to_string() + &db_name) {
-            Ok(()) => {
-                //dir had to be created.
-                dir = PathBuf::from("./".to_string() + &db_name);
-                let mut l0 = dir.clone();
-                l0.push("./l0");
-                create_dir(l0.clone());
-                let mut levels = Vec::new();
-                levels.push(l0.clone());
-                let mut max_level_size = Vec::new();
-                max_level_size.push(1024);
-                let mut curr_level_size = Vec::new();
-                curr_level_size.push(0);
-                SST {
-                    dir,
-                    levels,
-                    max_level_size,
-                    queue: VecDeque::new(),
-                    curr_level_size,
+    pub(crate) fn open<P: AsRef<Path>>(
+        dir: P,
+        options: Options,
+        snapshots: SnapshotList,
+    ) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        create_dir_all(dir.join("l0"))?;
+        let table_options = TableOptions::new(&options);
+        let mut on_disk = locate_tables(&dir)?;
+
+        let (manifest, state) = match Manifest::recover(&dir)? {
+            Some(recovered) => recovered,
+            None => {
+                // No manifest yet: adopt whatever tables the level
+                // directories already hold and record them as the first version.
+                let mut snapshot = VersionEdit::default();
+                let mut next_file_number = 1;
+                for (&number, (level, path)) in &on_disk {
+                    let table = Table::open(path, number, &table_options)?;
+                    snapshot
+                        .added
+                        .push(FileMeta::of(&table, (*level).min(MAX_LEVELS - 1)));
+                    next_file_number = next_file_number.max(number + 1);
                 }
+                snapshot.next_file_number = Some(next_file_number);
+                snapshot.last_sequence = Some(0);
+                let manifest = Manifest::create(&dir, 1, &snapshot)?;
+                let mut state = VersionState::default();
+                state.apply(snapshot);
+                (manifest, state)
+            }
+        };
+
+        let mut sst = SST {
+            dir,
+            levels: Vec::new(),
+            tables: Vec::new(),
+            max_level_size: Vec::new(),
+            curr_level_size: Vec::new(),
+            queue: VecDeque::new(),
+            next_file_number: Arc::new(AtomicU64::new(state.next_file_number)),
+            last_sequence: state.last_sequence,
+            snapshots,
+            compacting: HashSet::new(),
+            compact_pointer: Vec::new(),
+            manifest,
+            options,
+            table_options,
+        };
+        sst.add_level()?;
+        for (level, files) in state.levels.iter().enumerate() {
+            while sst.levels.len() <= level {
+                sst.add_level()?;
             }
-            Err(e) => {
-                //dir already exists
-                dir = PathBuf::from("./".to_string() + &db_name);
-
-                let children = dir.read_dir().unwrap();
-                let mut levels = Vec::new();
-                let mut curr_level_size = Vec::new();
-                let mut max_level_size = Vec::new();
-                let mut i: usize = 0;
-                for child in children {
-                    let child = child.unwrap();
-                    let path = child.path();
-                    if path.is_dir() {
-                        let level = path.clone();
-                        levels.push(path.clone());
-                        max_level_size.push(1024 * 10_i32.pow(i as u32) as usize);
-                        let curr_no_of_kvs_in_level = path.read_dir().unwrap().count();
-                        max_level_size[i] = curr_no_of_kvs_in_level;
-                        i += 1;
+            for meta in files {
+                let path = sst.levels[level].join(table_file_name(meta.number));
+                match on_disk.remove(&meta.number) {
+                    Some((_, found)) if found == path => {}
+                    // A trivial move renamed the file but crashed before logging it.
+                    Some((_, found)) => fs::rename(found, &path)?,
+                    None => {
+                        return Err(ShortDBErrors::CorruptedManifest(format!(
+                            "live table {} is missing",
+                            table_file_name(meta.number)
+                        )))
                     }
                 }
+                let table = Arc::new(Table::open(&path, meta.number, &sst.table_options)?);
+                sst.curr_level_size[level] += table.file_size as usize;
+                sst.tables[level].push(table);
+            }
+        }
+        // Whatever the manifest does not know about was never installed.
+        for (_, path) in on_disk.values() {
+            fs::remove_file(path)?;
+        }
+        sort_level0(&mut sst.tables[0]);
+        for level in sst.tables.iter_mut().skip(1) {
+            level.sort_by(|a, b| a.smallest_key.cmp(&b.smallest_key));
+        }
+
+        Ok(sst)
+    }
 
-                SST {
-                    dir,
-                    levels,
-                    max_level_size,
-                    curr_level_size,
-                    queue: VecDeque::new(),
+    pub(crate) fn add_level(&mut self) -> Result<()> {
+        let level = self.levels.len();
+        let path = self.dir.join(format!("l{}", level));
+        create_dir_all(&path)?;
+        self.levels.push(path);
+        self.tables.push(Vec::new());
+        self.max_level_size
+            .push(BASE_LEVEL_SIZE * 10_usize.pow(level.max(1) as u32 - 1));
+        self.curr_level_size.push(0);
+        self.compact_pointer.push(None);
+        Ok(())
+    }
+
+    /// The newest version of `key` no newer than `seq`, tombstones included,
+    /// looked up in the queued memtables and then level by level.
+    pub(crate) fn get_entry(&self, key: &[u8], seq: u64) -> Result<Option<Entry>> {
+        if let Some(entry) = self
+            .queue
+            .iter()
+            .rev()
+            .find_map(|(_, mem)| mem.get_entry(key, seq))
+        {
+            return Ok(Some(entry));
+        }
+        for (level, tables) in self.tables.iter().enumerate() {
+            let candidates: &[Arc<Table>] = if level == 0 {
+                tables
+            } else {
+                // Non-overlapping and sorted, so at most one table can hold the key.
+                let i = tables.partition_point(|t| t.largest_key.as_ref() < key);
+                &tables[i..tables.len().min(i + 1)]
+            };
+            for table in candidates {
+                if !table.may_contain(key) {
+                    continue;
+                }
+                if let Some(entry) = table.get(key, seq)? {
+                    return Ok(Some(entry));
                 }
             }
         }
+        Ok(None)
     }
 
-    pub(crate) fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        dbg!("looking in sst");
-        //get from sst
-        for level in self.levels.iter() {
-            dbg!(&level);
-            //get from level
-            let ssts: fs::ReadDir = level.read_dir().unwrap();
-            for sst in ssts {
-                let mut directory = level.clone();
-                let name = bytes_to_string(key);
-                directory.push(name.clone());
-                //get from sst
-                match Path::new(&directory).try_exists() {
-                    Ok(true) => {
-                        //file exists in this sst folder
-                        match fs::read(directory) {
-                            Ok(val) => {
-                                return Some(val);
-                            }
-                            Err(e) => {
-                                println!("some error happend{}", e);
-                            }
-                        }
-                    }
-                    Ok(false) => {
-                        //continue
-                    }
-                    Err(e) => {
-                        println!("error while seeking into sst files{}", e);
-                    }
-                }
+    /// Newest-first entry streams over every queued memtable and table,
+    /// each bounded to `range`.
+    pub(crate) fn sources(&self, range: &KeyRange) -> Vec<EntryIterator> {
+        let mut sources: Vec<EntryIterator> = self
+            .queue
+            .iter()
+            .rev()
+            .map(|(_, mem)| Box::new(mem.entries(range).into_iter().map(Ok)) as EntryIterator)
+            .collect();
+        for (level, tables) in self.tables.iter().enumerate() {
+            let in_range = tables
+                .iter()
+                .filter(|t| overlaps_range(range, &t.smallest_key, &t.largest_key))
+                .map(|t| t.iter_range(range.clone()));
+            if level == 0 {
+                sources.extend(in_range.map(|t| Box::new(t) as EntryIterator));
+            } else {
+                // Non-overlapping and sorted, so the level is one sorted run.
+                let run: Vec<TableIterator> = in_range.collect();
+                sources.push(Box::new(run.into_iter().flatten()));
             }
         }
-        return None;
+        sources
     }
 
-    pub(crate) fn set(&mut self) {
-        let mem = self.queue.pop_front().unwrap();
+    /// Reserves a file number for the next level 0 table and takes what
+    /// writing it needs, so the write itself can run without the SST.
+    pub(crate) fn prepare_flush(&mut self) -> PendingFlush {
+        let number = self.next_file_number.fetch_add(1, Ordering::Relaxed);
+        PendingFlush {
+            number,
+            path: self.levels[0].join(table_file_name(number)),
+            tmp_path: self.tmp_table_path(0, number),
+            compression: self.options.compression,
+            table_options: self.table_options.clone(),
+            retention: Retention::new(
+                self.snapshots.sequences(),
+                self.options.merge_operator.clone(),
+            ),
+        }
+    }
 
-        // Use iter() and access key and value from each entry
-        for entry in mem.memtable.iter() {
-            let key = entry.key();
-            let value = entry.value();
+    /// Installs the table written from the oldest queued memtable, which
+    /// covers every write of this family logged up to `last_sequence`, and
+    /// drops that memtable.
+    pub(crate) fn install_flush(
+        &mut self,
+        table: Option<Arc<Table>>,
+        last_sequence: u64,
+    ) -> Result<()> {
+        self.last_sequence = last_sequence;
+        if let Some(table) = table {
+            self.log_edit(VersionEdit {
+                added: vec![FileMeta::of(&table, 0)],
+                ..Default::default()
+            })?;
+            self.curr_level_size[0] += table.file_size as usize;
+            self.tables[0].insert(0, table);
+        }
+        self.queue.pop_front();
+        Ok(())
+    }
 
-            let mut path_of_kv_file = self.dir.clone();
-            path_of_kv_file.push("l0");
-            self.curr_level_size.push(0);
-            match path_of_kv_file.is_dir() {
-                false => {
-                    create_dir(&path_of_kv_file).expect("sorry couldnt create the folder");
-                }
-                true => {
-                    print!("folder already there");
+    /// Runs compactions on the calling thread until the tree is back in
+    /// shape for the configured strategy.
+    pub(crate) fn compact(&mut self) -> Result<()> {
+        while let Some(compaction) = self.pick_compaction()? {
+            match compaction.run(&|| false) {
+                Ok(outputs) => self.finish_compaction(&compaction, outputs)?,
+                Err(e) => {
+                    self.finish_compaction(&compaction, None)?;
+                    return Err(e);
                 }
             }
-            path_of_kv_file.push(bytes_to_string(key));
-            dbg!(&path_of_kv_file);
-            let mut file = File::create_new(&path_of_kv_file);
-            match file {
-                Ok(_) => {
-                    //file was not there
-                    file.unwrap().write_all(value).unwrap();
-                }
-                Err(_) => {
-                    //file was there overwrite file
-                    print!("most probably already existing");
-                    let mut file = OpenOptions::new()
-                        .write(true)
-                        .truncate(true)
-                        .open(path_of_kv_file);
-                    file.unwrap().write_all(value);
-                }
+        }
+        Ok(())
+    }
+
+    /// Durably records `edit`, stamped with the current file and sequence
+    /// counters. Nothing it adds is live, and nothing it removes may be
+    /// deleted, until this returns.
+    pub(crate) fn log_edit(&mut self, mut edit: VersionEdit) -> Result<()> {
+        edit.next_file_number = Some(self.next_file_number.load(Ordering::Relaxed));
+        edit.last_sequence = Some(self.last_sequence);
+        self.manifest.log_edit(&edit)?;
+        if self.manifest.needs_rotation() {
+            let mut snapshot = VersionEdit {
+                next_file_number: edit.next_file_number,
+                last_sequence: Some(self.last_sequence),
+                ..Default::default()
             };
+            for (level, tables) in self.tables.iter().enumerate() {
+                snapshot
+                    .added
+                    .extend(tables.iter().map(|t| FileMeta::of(t, level)));
+            }
+            // `edit` may not be applied to `tables` yet; fold it in as well.
+            snapshot
+                .added
+                .retain(|f| !edit.deleted.contains(&(f.level, f.number)));
+            snapshot.added.extend(edit.added);
+            self.manifest.rotate(&snapshot)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn tmp_table_path(&self, level: usize, number: u64) -> PathBuf {
+        self.levels[level].join(format!("{}.tmp", table_file_name(number)))
+    }
+}
+
+/// A level 0 table about to be written from a memtable.
+pub(crate) struct PendingFlush {
+    number: u64,
+    path: PathBuf,
+    tmp_path: PathBuf,
+    compression: CompressionType,
+    table_options: TableOptions,
+    retention: Retention,
+}
 
-            self.curr_level_size[0] += 1;
-            if self.curr_level_size >= self.max_level_size {
-                self.compact();
+impl PendingFlush {
+    /// Writes `mem` out, keeping the versions live snapshots still need.
+    /// An empty memtable leaves no table behind.
+    pub(crate) fn write(self, mem: &Memtable) -> Result<Option<Arc<Table>>> {
+        if mem.memtable.is_empty() {
+            return Ok(None);
+        }
+        let mut builder = TableBuilder::new(&self.tmp_path, self.compression, &self.table_options)?;
+        let entries = mem.entries(&(Bound::Unbounded, Bound::Unbounded));
+        for versions in entries.chunk_by(|a, b| a.key == b.key) {
+            let mut versions = versions.to_vec();
+            self.retention.retain(&mut versions, false);
+            for entry in &versions {
+                builder.add(entry)?;
             }
         }
+        builder.finish()?;
+        fs::rename(&self.tmp_path, &self.path)?;
+        let table = Table::open(&self.path, self.number, &self.table_options)?;
+        Ok(Some(Arc::new(table)))
     }
+}
+
+/// Orders level 0 newest first. Runs are ranked by the newest write they
+/// hold, falling back to file number for tables without that property.
+pub(crate) fn sort_level0(tables: &mut [Arc<Table>]) {
+    tables.sort_by_key(|t| std::cmp::Reverse((t.largest_seq, t.file_number)));
+}
+
+pub(crate) fn table_file_name(number: u64) -> String {
+    format!("{:06}.sst", number)
+}
 
-    pub(crate) fn compact(&self) {
-        print!("ok compacted");
-        // todo!()
+impl FileMeta {
+    pub(crate) fn of(table: &Table, level: usize) -> Self {
+        FileMeta {
+            level,
+            number: table.file_number,
+            size: table.file_size,
+            smallest_key: table.smallest_key.to_vec(),
+            largest_key: table.largest_key.to_vec(),
+        }
     }
+}
 
-    pub(crate) fn delete(&mut self, key: &[u8]) -> Result<(), Error> {
-        for level in self.levels.iter() {
-            dbg!(&level);
-            let ssts: fs::ReadDir = level.read_dir()?;
-            for sst in ssts {
-                let mut directory = level.clone();
-                let name = bytes_to_string(key);
-                directory.push(name.clone());
-                if Path::new(&directory).exists() {
-                    remove_file(&directory)?;
-                    return Ok(());
-                }
+/// Finds every table file under the `l<n>` directories, keyed by file
+/// number, and clears out temporary files of unfinished writes.
+fn locate_tables(dir: &Path) -> Result<HashMap<u64, (usize, PathBuf)>> {
+    let mut found = HashMap::new();
+    for child in dir.read_dir()? {
+        let level_dir = child?.path();
+        let level = level_dir
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_prefix('l'))
+            .and_then(|n| n.parse::<usize>().ok());
+        let level = match level {
+            Some(level) if level_dir.is_dir() => level,
+            _ => continue,
+        };
+        for child in level_dir.read_dir()? {
+            let path = child?.path();
+            let file_name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            if file_name.ends_with(".tmp") {
+                fs::remove_file(&path)?;
+                continue;
+            }
+            if let Some(number) = file_name
+                .strip_suffix(".sst")
+                .and_then(|n| n.parse::<u64>().ok())
+            {
+                found.insert(number, (level, path));
             }
         }
-        Ok(())
     }
+    Ok(found)
 }
@@ -0,0 +1,109 @@
+//! Write stalls.
+//!
+//! Flushes and compactions run in the background, so nothing stops writes
+//! from outrunning them: Memtables waiting for the flush thread pile up in
+//! memory, and level 0 tables, which every read has to check, pile up on
+//! disk. Once either passes its slowdown threshold, every write is held
+//! back for a moment to let the background catch up. Once either reaches
+//! its stop threshold, writes wait until it drops back again, and give up
+//! with [`ShortDBErrors::WriteStall`] after
+//! [`Options::write_stall_timeout`].
+//!
+//! [`ShortDBErrors::WriteStall`]: crate::errors::ShortDBErrors::WriteStall
+//! [`Options::write_stall_timeout`]: super::options::Options::write_stall_timeout
+
+use super::column_family::ColumnFamily;
+use std::fmt;
+use std::time::Duration;
+
+/// How long each write is held back while slowed down.
+pub(crate) const SLOWDOWN_DELAY: Duration = Duration::from_millis(1);
+/// How often a stopped write checks whether it may go on.
+pub(crate) const STOP_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// What a stalled write is waiting for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StallReason {
+    /// Too many full Memtables are waiting to be flushed.
+    ImmutableMemtables,
+    /// Too many tables in level 0 are waiting to be compacted.
+    Level0Files,
+}
+
+impl fmt::Display for StallReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StallReason::ImmutableMemtables => "too many memtables waiting to be flushed",
+            StallReason::Level0Files => "too many level 0 tables waiting to be compacted",
+        })
+    }
+}
+
+/// Counters reported by [`crate::ShorterDB::stall_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StallStats {
+    /// Writes held back for a moment while slowed down.
+    pub delayed_writes: u64,
+    /// Writes that had to wait for background work to catch up.
+    pub stopped_writes: u64,
+    /// Writes that gave up with `WriteStall`.
+    pub timed_out_writes: u64,
+    /// Time writes spent held back because of full Memtables.
+    pub immutable_memtables_stall: Duration,
+    /// Time writes spent held back because of level 0 tables.
+    pub level0_files_stall: Duration,
+    /// Reason and length of the most recent stall.
+    pub last_stall: Option<(StallReason, Duration)>,
+}
+
+impl StallStats {
+    /// Accounts for one write held back for `waited`.
+    pub(crate) fn record(&mut self, stall: Stall, waited: Duration) {
+        match stall {
+            Stall::Delay(_) => self.delayed_writes += 1,
+            Stall::Stop(_) => self.stopped_writes += 1,
+        }
+        let reason = stall.reason();
+        match reason {
+            StallReason::ImmutableMemtables => self.immutable_memtables_stall += waited,
+            StallReason::Level0Files => self.level0_files_stall += waited,
+        }
+        self.last_stall = Some((reason, waited));
+    }
+}
+
+/// How much a family holds writes back, if at all.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Stall {
+    Delay(StallReason),
+    Stop(StallReason),
+}
+
+impl Stall {
+    pub(crate) fn reason(&self) -> StallReason {
+        match self {
+            Stall::Delay(reason) | Stall::Stop(reason) => *reason,
+        }
+    }
+}
+
+impl ColumnFamily {
+    /// Whether writes have to be held back until this family's flushes or
+    /// compactions catch up.
+    pub(crate) fn stall(&self) -> Option<Stall> {
+        let options = &self.sst.options;
+        let queued = self.sst.queue.len();
+        let level0 = self.sst.tables[0].len();
+        if queued >= options.max_immutable_memtables {
+            Some(Stall::Stop(StallReason::ImmutableMemtables))
+        } else if level0 >= options.level0_stop_writes_trigger {
+            Some(Stall::Stop(StallReason::Level0Files))
+        } else if queued + 1 >= options.max_immutable_memtables && queued > 0 {
+            Some(Stall::Delay(StallReason::ImmutableMemtables))
+        } else if level0 >= options.level0_slowdown_writes_trigger {
+            Some(Stall::Delay(StallReason::Level0Files))
+        } else {
+            None
+        }
+    }
+}
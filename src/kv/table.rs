@@ -0,0 +1,692 @@
+//! On-disk sorted string table.
+//!
+//! Every memtable flush produces one immutable table file:
+//!
+//! ```text
+//! [data block 0] ... [data block n-1]
+//! [properties block]
+//! [metaindex block]   name -> handle of each meta block
+//! [index block]       last key of data block i -> handle of data block i
+//! [footer]            metaindex handle, index handle, format version, magic
+//! ```
+//!
+//! Every block uses the layout from [`super::block`] and a handle is an
+//! `(offset u64, size u64)` pair, little endian. The exception is the
+//! optional `filter.bloom` meta block, which holds a bincode encoded bloom
+//! filter over every key in the table. Since format version 2, data blocks
+//! are followed by the id of the codec they were compressed with, see
+//! [`super::compression`]. Since format version 3, every entry carries the
+//! sequence number of its write and a key may appear once per version,
+//! newest first; the index block is keyed by the last version in each block.
+//! Since format version 4, every entry also carries its expiry time. The
+//! `largest_seq` property is optional and read as 0 when missing.
+
+use super::block::{Block, BlockBuilder, Entry, ValueKind, BLOCK_SIZE};
+use super::cache::BlockCache;
+use super::compression::Codecs;
+use super::iterator::KeyRange;
+use super::options::{CompressionType, Options};
+use crate::errors::{Result, ShortDBErrors};
+use bloomfilter::Bloom;
+use bytes::Bytes;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub(crate) const TABLE_MAGIC: u64 = 0x5348_4f52_5444_4253; // "SHORTDBS"
+pub(crate) const FORMAT_VERSION: u32 = 4;
+/// Version 1 tables store data blocks uncompressed and without a codec id.
+const FIRST_COMPRESSED_VERSION: u32 = 2;
+const FOOTER_SIZE: usize = 16 + 16 + 4 + 8;
+const PROPERTIES_BLOCK: &[u8] = b"properties";
+const FILTER_BLOCK: &[u8] = b"filter.bloom";
+/// Target false positive rate of the per-table bloom filter.
+const FILTER_FP_RATE: f64 = 0.01;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BlockHandle {
+    pub(crate) offset: u64,
+    pub(crate) size: u64,
+}
+
+impl BlockHandle {
+    fn encode(&self) -> [u8; 16] {
+        let mut out = [0; 16];
+        out[..8].copy_from_slice(&self.offset.to_le_bytes());
+        out[8..].copy_from_slice(&self.size.to_le_bytes());
+        out
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() != 16 {
+            return Err(ShortDBErrors::CorruptedSST("bad block handle".to_string()));
+        }
+        Ok(BlockHandle {
+            offset: u64::from_le_bytes(data[..8].try_into().unwrap()),
+            size: u64::from_le_bytes(data[8..].try_into().unwrap()),
+        })
+    }
+}
+
+/// How the tables of one database read their blocks.
+#[derive(Clone, Default)]
+pub(crate) struct TableOptions {
+    pub(crate) mmap_reads: bool,
+    /// The block cache and this database's id within it.
+    pub(crate) block_cache: Option<(Arc<BlockCache>, u64)>,
+    pub(crate) codecs: Codecs,
+}
+
+impl TableOptions {
+    pub(crate) fn new(options: &Options) -> Self {
+        TableOptions {
+            mmap_reads: options.mmap_reads,
+            block_cache: options
+                .block_cache
+                .as_ref()
+                .map(|cache| (Arc::clone(cache), cache.register())),
+            codecs: Codecs::new(options),
+        }
+    }
+}
+
+/// Streams sorted entries into a new table file.
+pub(crate) struct TableBuilder {
+    file: BufWriter<File>,
+    offset: u64,
+    data_block: BlockBuilder,
+    index_block: BlockBuilder,
+    last_key: Vec<u8>,
+    last_seq: u64,
+    largest_seq: u64,
+    smallest_key: Option<Vec<u8>>,
+    num_entries: u64,
+    filter_keys: Vec<Vec<u8>>,
+    compression: CompressionType,
+    codecs: Codecs,
+}
+
+impl TableBuilder {
+    pub(crate) fn new<P: AsRef<Path>>(
+        path: P,
+        compression: CompressionType,
+        options: &TableOptions,
+    ) -> Result<Self> {
+        let file = File::create(path)?;
+        Ok(TableBuilder {
+            file: BufWriter::new(file),
+            offset: 0,
+            data_block: BlockBuilder::new(),
+            index_block: BlockBuilder::new(),
+            last_key: Vec::new(),
+            last_seq: 0,
+            largest_seq: 0,
+            smallest_key: None,
+            num_entries: 0,
+            filter_keys: Vec::new(),
+            compression,
+            codecs: options.codecs.clone(),
+        })
+    }
+
+    /// Adds an entry. Keys must arrive in ascending order, and versions of
+    /// the same key newest first.
+    pub(crate) fn add(&mut self, entry: &Entry) -> Result<()> {
+        let (key, seq) = (entry.key.as_ref(), entry.seq);
+        let new_key = self.smallest_key.is_none() || key != self.last_key.as_slice();
+        debug_assert!(
+            self.smallest_key.is_none()
+                || key > self.last_key.as_slice()
+                || (!new_key && seq < self.last_seq)
+        );
+        if self.smallest_key.is_none() {
+            self.smallest_key = Some(key.to_vec());
+        }
+        self.data_block
+            .add(key, seq, entry.kind, entry.expires_at, &entry.value);
+        if new_key {
+            self.last_key.clear();
+            self.last_key.extend_from_slice(key);
+            self.filter_keys.push(key.to_vec());
+        }
+        self.last_seq = seq;
+        self.largest_seq = self.largest_seq.max(seq);
+        self.num_entries += 1;
+
+        if self.data_block.estimated_size() >= BLOCK_SIZE {
+            self.flush_data_block()?;
+        }
+        Ok(())
+    }
+
+    /// Bytes written so far plus the pending data block.
+    pub(crate) fn estimated_size(&self) -> u64 {
+        self.offset + self.data_block.estimated_size() as u64
+    }
+
+    fn flush_data_block(&mut self) -> Result<()> {
+        if self.data_block.is_empty() {
+            return Ok(());
+        }
+        let block = std::mem::replace(&mut self.data_block, BlockBuilder::new());
+        let handle =
+            self.write_block(self.codecs.encode_block(block.finish(), self.compression)?)?;
+        self.index_block.add(
+            &self.last_key,
+            self.last_seq,
+            ValueKind::Put,
+            None,
+            &handle.encode(),
+        );
+        Ok(())
+    }
+
+    fn write_block(&mut self, data: Vec<u8>) -> Result<BlockHandle> {
+        let handle = BlockHandle {
+            offset: self.offset,
+            size: data.len() as u64,
+        };
+        self.file.write_all(&data)?;
+        self.offset += data.len() as u64;
+        Ok(handle)
+    }
+
+    /// Writes the meta blocks, index and footer and syncs the file.
+    /// Returns the final file size.
+    pub(crate) fn finish(mut self) -> Result<u64> {
+        self.flush_data_block()?;
+
+        let mut properties = BlockBuilder::new();
+        properties.add(b"largest_key", 0, ValueKind::Put, None, &self.last_key);
+        properties.add(
+            b"largest_seq",
+            0,
+            ValueKind::Put,
+            None,
+            &self.largest_seq.to_le_bytes(),
+        );
+        properties.add(
+            b"num_entries",
+            0,
+            ValueKind::Put,
+            None,
+            &self.num_entries.to_le_bytes(),
+        );
+        properties.add(
+            b"smallest_key",
+            0,
+            ValueKind::Put,
+            None,
+            self.smallest_key.as_deref().unwrap_or_default(),
+        );
+        let properties_handle = self.write_block(properties.finish())?;
+
+        // Meta blocks are listed in key order: "filter.bloom" < "properties".
+        let mut metaindex = BlockBuilder::new();
+        if !self.filter_keys.is_empty() {
+            let mut filter: Bloom<[u8]> =
+                Bloom::new_for_fp_rate(self.filter_keys.len(), FILTER_FP_RATE);
+            for key in &self.filter_keys {
+                filter.set(key);
+            }
+            let encoded = bincode::serialize(&filter).map_err(|e| {
+                ShortDBErrors::CorruptedSST(format!("could not encode filter: {}", e))
+            })?;
+            let filter_handle = self.write_block(encoded)?;
+            metaindex.add(
+                FILTER_BLOCK,
+                0,
+                ValueKind::Put,
+                None,
+                &filter_handle.encode(),
+            );
+        }
+        metaindex.add(
+            PROPERTIES_BLOCK,
+            0,
+            ValueKind::Put,
+            None,
+            &properties_handle.encode(),
+        );
+        let metaindex_handle = self.write_block(metaindex.finish())?;
+
+        let index = std::mem::replace(&mut self.index_block, BlockBuilder::new());
+        let index_handle = self.write_block(index.finish())?;
+
+        let mut footer = Vec::with_capacity(FOOTER_SIZE);
+        footer.extend_from_slice(&metaindex_handle.encode());
+        footer.extend_from_slice(&index_handle.encode());
+        footer.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        footer.extend_from_slice(&TABLE_MAGIC.to_le_bytes());
+        self.file.write_all(&footer)?;
+        self.offset += footer.len() as u64;
+
+        self.file.flush()?;
+        self.file.get_ref().sync_all()?;
+        Ok(self.offset)
+    }
+}
+
+/// Read handle on a finished table file.
+pub(crate) struct Table {
+    pub(crate) file_number: u64,
+    pub(crate) path: PathBuf,
+    pub(crate) file_size: u64,
+    pub(crate) smallest_key: Bytes,
+    pub(crate) largest_key: Bytes,
+    /// Newest sequence number in the table, 0 for tables written before
+    /// the property existed.
+    pub(crate) largest_seq: u64,
+    reader: TableReader,
+    version: u32,
+    index: Block,
+    filter: Option<Bloom<[u8]>>,
+    block_cache: Option<(Arc<BlockCache>, u64)>,
+    codecs: Codecs,
+}
+
+impl Table {
+    pub(crate) fn open<P: AsRef<Path>>(
+        path: P,
+        file_number: u64,
+        options: &TableOptions,
+    ) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::open(&path)?;
+        let file_size = file.metadata()?.len();
+        if file_size < FOOTER_SIZE as u64 {
+            return Err(ShortDBErrors::CorruptedSST(format!(
+                "{:?} is too small to be a table",
+                path
+            )));
+        }
+
+        let reader = TableReader::new(file, options.mmap_reads);
+
+        let footer = reader.read(file_size - FOOTER_SIZE as u64, FOOTER_SIZE)?;
+        let magic = u64::from_le_bytes(footer[36..44].try_into().unwrap());
+        if magic != TABLE_MAGIC {
+            return Err(ShortDBErrors::CorruptedSST(format!(
+                "{:?} has a bad magic number",
+                path
+            )));
+        }
+        let version = u32::from_le_bytes(footer[32..36].try_into().unwrap());
+        if !(1..=FORMAT_VERSION).contains(&version) {
+            return Err(ShortDBErrors::UnsupportedSSTVersion(version));
+        }
+        let metaindex_handle = BlockHandle::decode(&footer[..16])?;
+        let index_handle = BlockHandle::decode(&footer[16..32])?;
+
+        let index = Block::decode(reader.read_block(index_handle)?, version)?;
+        let metaindex = Block::decode(reader.read_block(metaindex_handle)?, version)?;
+
+        let properties = match find(&metaindex, PROPERTIES_BLOCK)? {
+            Some(entry) => {
+                let handle = BlockHandle::decode(&entry.value)?;
+                Block::decode(reader.read_block(handle)?, version)?
+            }
+            None => {
+                return Err(ShortDBErrors::CorruptedSST(
+                    "missing properties block".to_string(),
+                ))
+            }
+        };
+        // Copied out of the mapping, if any: keys like these outlive the
+        // table, as compaction pointers for one.
+        let property = |name: &[u8]| -> Result<Bytes> {
+            find(&properties, name)?
+                .map(|e| Bytes::copy_from_slice(&e.value))
+                .ok_or_else(|| {
+                    ShortDBErrors::CorruptedSST(format!(
+                        "missing property {}",
+                        String::from_utf8_lossy(name)
+                    ))
+                })
+        };
+        let largest_seq = match find(&properties, b"largest_seq")? {
+            Some(entry) => u64::from_le_bytes(entry.value.as_ref().try_into().map_err(|_| {
+                ShortDBErrors::CorruptedSST("bad largest_seq property".to_string())
+            })?),
+            None => 0,
+        };
+        let filter =
+            match find(&metaindex, FILTER_BLOCK)? {
+                Some(entry) => {
+                    let data = reader.read_block(BlockHandle::decode(&entry.value)?)?;
+                    Some(bincode::deserialize(&data).map_err(|e| {
+                        ShortDBErrors::CorruptedSST(format!("bad filter block: {}", e))
+                    })?)
+                }
+                None => None,
+            };
+
+        Ok(Table {
+            file_number,
+            path,
+            smallest_key: property(b"smallest_key")?,
+            largest_key: property(b"largest_key")?,
+            largest_seq,
+            file_size,
+            reader,
+            version,
+            index,
+            filter,
+            block_cache: options.block_cache.clone(),
+            codecs: options.codecs.clone(),
+        })
+    }
+
+    /// False if the table definitely does not contain `key`. Tables written
+    /// without a filter block always answer true.
+    pub(crate) fn may_contain(&self, key: &[u8]) -> bool {
+        if key < self.smallest_key.as_ref() || key > self.largest_key.as_ref() {
+            return false;
+        }
+        self.filter.as_ref().is_none_or(|filter| filter.check(key))
+    }
+
+    /// Looks up the newest version of `key` no newer than `seq`, returning
+    /// the stored entry (which may be a tombstone).
+    pub(crate) fn get(&self, key: &[u8], seq: u64) -> Result<Option<Entry>> {
+        if key < self.smallest_key.as_ref() || key > self.largest_key.as_ref() {
+            return Ok(None);
+        }
+        // The index is keyed by the last entry of every data block, so the
+        // first index entry at or past the version we want points at the
+        // only block that can hold it.
+        let i = self.index.seek_version(key, seq)?;
+        if i == self.index.len() {
+            return Ok(None);
+        }
+        let block = self.data_block(i)?;
+        let pos = block.seek_version(key, seq)?;
+        if pos == block.len() || block.key_at(pos)? != key {
+            return Ok(None);
+        }
+        block.entry(pos).map(Some)
+    }
+
+    fn data_block(&self, i: usize) -> Result<Arc<Block>> {
+        let handle = BlockHandle::decode(&self.index.entry(i)?.value)?;
+        let cached = self
+            .block_cache
+            .as_ref()
+            .map(|(cache, id)| (cache, (*id, self.file_number, handle.offset)));
+        if let Some((cache, key)) = cached {
+            if let Some(block) = cache.get(key) {
+                return Ok(block);
+            }
+        }
+
+        let data = self.reader.read_block(handle)?;
+        let data = if self.version < FIRST_COMPRESSED_VERSION {
+            data
+        } else {
+            self.codecs.decode_block(data)?
+        };
+        // A cached block can outlive its table, so it must not hold on to
+        // the whole mapping; blocks read past the cache still borrow it.
+        let data = match cached {
+            Some(_) => self.reader.detach(data),
+            None => data,
+        };
+        let block = Arc::new(Block::decode(data, self.version)?);
+        if let Some((cache, key)) = cached {
+            cache.insert(key, Arc::clone(&block));
+        }
+        Ok(block)
+    }
+
+    pub(crate) fn overlaps(&self, smallest: &[u8], largest: &[u8]) -> bool {
+        self.smallest_key.as_ref() <= largest && self.largest_key.as_ref() >= smallest
+    }
+
+    /// Iterates every entry of the table in key order.
+    pub(crate) fn iter(self: &Arc<Self>) -> TableIterator {
+        self.iter_range((Bound::Unbounded, Bound::Unbounded))
+    }
+
+    /// Iterates the entries of the table within `range`, from either end.
+    pub(crate) fn iter_range(self: &Arc<Self>, range: KeyRange) -> TableIterator {
+        TableIterator {
+            table: Arc::clone(self),
+            range: Some(range),
+            front: (0, 0),
+            back: (0, 0),
+            front_block: None,
+            back_block: None,
+            done: false,
+        }
+    }
+}
+
+/// `(data block, entry within the block)`. Positions are kept canonical,
+/// either on an existing entry or at `(number of blocks, 0)`, so that two
+/// cursors compare equal exactly when they meet.
+type Position = (usize, usize);
+
+pub(crate) struct TableIterator {
+    table: Arc<Table>,
+    /// Bounds not yet resolved to positions. Resolving reads blocks, so it
+    /// is deferred to the first call to let errors surface through the
+    /// iterator.
+    range: Option<KeyRange>,
+    /// The entry `next` yields.
+    front: Position,
+    /// One past the entry `next_back` yields.
+    back: Position,
+    front_block: Option<(usize, Arc<Block>)>,
+    back_block: Option<(usize, Arc<Block>)>,
+    done: bool,
+}
+
+impl TableIterator {
+    fn resolve(&mut self, range: KeyRange) -> Result<()> {
+        self.front = match &range.0 {
+            Bound::Included(key) => self.lower_bound(key, false)?,
+            Bound::Excluded(key) => self.lower_bound(key, true)?,
+            Bound::Unbounded => (0, 0),
+        };
+        self.back = match &range.1 {
+            Bound::Included(key) => self.lower_bound(key, true)?,
+            Bound::Excluded(key) => self.lower_bound(key, false)?,
+            Bound::Unbounded => (self.table.index.len(), 0),
+        };
+        Ok(())
+    }
+
+    /// Position of the first entry with a key `>= key`, or `> key` with
+    /// `skip_equal`.
+    fn lower_bound(&mut self, key: &[u8], skip_equal: bool) -> Result<Position> {
+        let seek = |block: &Block| {
+            if skip_equal {
+                block.seek_after(key)
+            } else {
+                block.seek(key)
+            }
+        };
+        // The index is keyed by the last key of every block, so block `i`
+        // is the first whose keys reach `key`.
+        let i = seek(&self.table.index)?;
+        if i == self.table.index.len() {
+            return Ok((i, 0));
+        }
+        let block = load(&self.table, &mut self.front_block, i)?;
+        let pos = seek(&block)?;
+        Ok(if pos < block.len() {
+            (i, pos)
+        } else {
+            (i + 1, 0)
+        })
+    }
+
+    fn next_entry(&mut self) -> Result<Entry> {
+        let (i, pos) = self.front;
+        let block = load(&self.table, &mut self.front_block, i)?;
+        let entry = block.entry(pos)?;
+        self.front = if pos + 1 < block.len() {
+            (i, pos + 1)
+        } else {
+            (i + 1, 0)
+        };
+        Ok(entry)
+    }
+
+    fn prev_entry(&mut self) -> Result<Entry> {
+        let (mut i, mut pos) = self.back;
+        if pos == 0 {
+            i -= 1;
+            let block = load(&self.table, &mut self.back_block, i)?;
+            pos = block.len();
+        }
+        let block = load(&self.table, &mut self.back_block, i)?;
+        pos = pos
+            .checked_sub(1)
+            .ok_or_else(|| ShortDBErrors::CorruptedSST("empty data block".to_string()))?;
+        self.back = (i, pos);
+        block.entry(pos)
+    }
+
+    /// Runs one step unless the cursors have met, stopping for good after
+    /// reporting an error instead of retrying forever.
+    fn step(&mut self, forward: bool) -> Option<Result<Entry>> {
+        if self.done {
+            return None;
+        }
+        if let Some(range) = self.range.take() {
+            if let Err(e) = self.resolve(range) {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+        if self.front >= self.back {
+            self.done = true;
+            return None;
+        }
+        let entry = if forward {
+            self.next_entry()
+        } else {
+            self.prev_entry()
+        };
+        self.done = entry.is_err();
+        Some(entry)
+    }
+}
+
+/// Returns block `i`, reusing the one held in `slot` if it is the same.
+fn load(table: &Table, slot: &mut Option<(usize, Arc<Block>)>, i: usize) -> Result<Arc<Block>> {
+    if let Some((loaded, block)) = slot {
+        if *loaded == i {
+            return Ok(Arc::clone(block));
+        }
+    }
+    let block = table.data_block(i)?;
+    *slot = Some((i, Arc::clone(&block)));
+    Ok(block)
+}
+
+impl Iterator for TableIterator {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.step(true)
+    }
+}
+
+impl DoubleEndedIterator for TableIterator {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.step(false)
+    }
+}
+
+fn find(block: &Block, key: &[u8]) -> Result<Option<Entry>> {
+    let i = block.seek(key)?;
+    if i == block.len() {
+        return Ok(None);
+    }
+    let entry = block.entry(i)?;
+    Ok((entry.key == key).then_some(entry))
+}
+
+/// Where block reads are served from. Table files never change once
+/// written, so a read-only mapping of the whole file stays valid for as long
+/// as the table is open.
+enum TableReader {
+    /// The whole mapped file; blocks are slices of it, not copies.
+    Mmap(Bytes),
+    File(File),
+}
+
+impl TableReader {
+    /// Maps `file` if asked to, falling back to `pread` when mapping fails.
+    fn new(file: File, mmap: bool) -> Self {
+        if mmap {
+            // SAFETY: table files are written once under a temporary name and
+            // only ever renamed or deleted afterwards, never modified.
+            if let Ok(map) = unsafe { Mmap::map(&file) } {
+                #[cfg(unix)]
+                let _ = map.advise(memmap2::Advice::Random);
+                return TableReader::Mmap(Bytes::from_owner(map));
+            }
+        }
+        TableReader::File(file)
+    }
+
+    fn read(&self, offset: u64, len: usize) -> Result<Bytes> {
+        match self {
+            TableReader::Mmap(map) => {
+                let start = usize::try_from(offset).unwrap_or(usize::MAX);
+                match start.checked_add(len) {
+                    Some(end) if end <= map.len() => Ok(map.slice(start..end)),
+                    _ => Err(ShortDBErrors::CorruptedSST(
+                        "block handle past end of file".to_string(),
+                    )),
+                }
+            }
+            TableReader::File(file) => {
+                let mut buf = vec![0; len];
+                read_exact_at(file, &mut buf, offset)?;
+                Ok(Bytes::from(buf))
+            }
+        }
+    }
+
+    fn read_block(&self, handle: BlockHandle) -> Result<Bytes> {
+        self.read(handle.offset, handle.size as usize)
+    }
+
+    /// Copies `data` out of the mapping if it is a slice of it.
+    fn detach(&self, data: Bytes) -> Bytes {
+        match self {
+            TableReader::Mmap(map) if map.as_ptr_range().contains(&data.as_ptr()) => {
+                Bytes::copy_from_slice(&data)
+            }
+            _ => data,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,195 @@
+use super::batch::WriteBatch;
+use super::db::ShorterDB;
+use super::lock::LockManager;
+use super::snapshot::Snapshot;
+use crate::errors::{Result, ShortDBErrors};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a pessimistic transaction waits for a lock unless told otherwise.
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A read-modify-write transaction that takes no locks, returned by
+/// [`ShorterDB::begin_optimistic`].
+///
+/// Reads see the database as of the moment the transaction began, plus the
+/// transaction's own writes. Writes are buffered and applied as one atomic
+/// batch by [`OptimisticTransaction::commit`], which first checks that no
+/// key the transaction read or wrote has changed since it began, and fails
+/// with [`ShortDBErrors::Conflict`] otherwise.
+pub struct OptimisticTransaction {
+    snapshot: Snapshot,
+    /// Buffered writes; `None` deletes the key.
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    reads: BTreeSet<Vec<u8>>,
+}
+
+impl OptimisticTransaction {
+    pub(crate) fn new(snapshot: Snapshot) -> Self {
+        OptimisticTransaction {
+            snapshot,
+            writes: BTreeMap::new(),
+            reads: BTreeSet::new(),
+        }
+    }
+
+    /// Like [`ShorterDB::get`], but sees the transaction's own writes and
+    /// nothing written by others since it began.
+    pub fn get(&mut self, db: &ShorterDB, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.writes.get(key) {
+            return Ok(value.clone());
+        }
+        self.reads.insert(key.to_vec());
+        self.snapshot.get(db, key)
+    }
+
+    pub fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.writes.insert(key.to_vec(), Some(value.to_vec()));
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        self.writes.insert(key.to_vec(), None);
+    }
+
+    /// Applies the buffered writes atomically, unless another write changed
+    /// a key this transaction read or wrote after it began, in which case
+    /// nothing is applied and [`ShortDBErrors::Conflict`] is returned.
+    pub fn commit(self, db: &ShorterDB) -> Result<()> {
+        // Hold the write lock so nothing commits between the check and the
+        // write.
+        let mut wal = db.lock_writes();
+        for key in self.reads.iter().chain(self.writes.keys()) {
+            if db
+                .latest_sequence(key)?
+                .is_some_and(|seq| seq > self.snapshot.sequence())
+            {
+                return Err(ShortDBErrors::Conflict);
+            }
+        }
+        let mut batch = WriteBatch::new();
+        for (key, value) in &self.writes {
+            match value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            };
+        }
+        db.write_locked(&mut wal, batch)
+    }
+
+    /// Discards the buffered writes.
+    pub fn rollback(self) {}
+}
+
+/// A transaction that locks every key it reads for update or writes,
+/// returned by [`ShorterDB::begin_pessimistic`].
+///
+/// Locks are exclusive and held until the transaction commits or rolls back,
+/// so a commit never conflicts. A transaction asking for a key another one
+/// holds waits for it, failing with [`ShortDBErrors::LockTimeout`] after the
+/// lock timeout, or at once with [`ShortDBErrors::Deadlock`] if the holder is
+/// itself waiting, directly or not, on this transaction. Locks only exclude
+/// other pessimistic transactions, not plain writes to the database.
+///
+/// Writes are buffered until commit. [`PessimisticTransaction::set_savepoint`]
+/// marks a point that [`PessimisticTransaction::rollback_to_savepoint`] later
+/// undoes the writes back to; locks taken since are kept.
+pub struct PessimisticTransaction {
+    id: u64,
+    locks: Arc<LockManager>,
+    lock_timeout: Duration,
+    held: BTreeSet<Vec<u8>>,
+    /// Buffered writes in the order they were made; `None` deletes the key.
+    writes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    /// Length of `writes` at each savepoint, oldest first.
+    savepoints: Vec<usize>,
+}
+
+impl PessimisticTransaction {
+    pub(crate) fn new(locks: Arc<LockManager>) -> Self {
+        PessimisticTransaction {
+            id: locks.begin(),
+            locks,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            held: BTreeSet::new(),
+            writes: Vec::new(),
+            savepoints: Vec::new(),
+        }
+    }
+
+    /// Sets how long later lock requests wait before giving up.
+    pub fn set_lock_timeout(&mut self, timeout: Duration) {
+        self.lock_timeout = timeout;
+    }
+
+    fn lock(&mut self, key: &[u8]) -> Result<()> {
+        if !self.held.contains(key) {
+            self.locks.lock(self.id, key, self.lock_timeout)?;
+            self.held.insert(key.to_vec());
+        }
+        Ok(())
+    }
+
+    /// Like [`ShorterDB::get`], but sees the transaction's own writes first.
+    /// Takes no lock.
+    pub fn get(&self, db: &ShorterDB, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.writes.iter().rev().find(|(k, _)| k == key) {
+            Some((_, value)) => Ok(value.clone()),
+            None => db.get(key),
+        }
+    }
+
+    /// Locks `key` until the transaction ends, then reads it.
+    pub fn get_for_update(&mut self, db: &ShorterDB, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.lock(key)?;
+        self.get(db, key)
+    }
+
+    /// Locks `key` until the transaction ends and buffers the write.
+    pub fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.lock(key)?;
+        self.writes.push((key.to_vec(), Some(value.to_vec())));
+        Ok(())
+    }
+
+    /// Locks `key` until the transaction ends and buffers its deletion.
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.lock(key)?;
+        self.writes.push((key.to_vec(), None));
+        Ok(())
+    }
+
+    /// Marks the current point of the transaction. Savepoints nest.
+    pub fn set_savepoint(&mut self) {
+        self.savepoints.push(self.writes.len());
+    }
+
+    /// Undoes every write made since the most recent savepoint and removes
+    /// it. Fails with [`ShortDBErrors::NoSavepoint`] if there is none.
+    pub fn rollback_to_savepoint(&mut self) -> Result<()> {
+        let len = self.savepoints.pop().ok_or(ShortDBErrors::NoSavepoint)?;
+        self.writes.truncate(len);
+        Ok(())
+    }
+
+    /// Applies the buffered writes atomically and releases the locks.
+    pub fn commit(self, db: &ShorterDB) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        for (key, value) in &self.writes {
+            match value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            };
+        }
+        db.write(batch)
+    }
+
+    /// Discards the buffered writes and releases the locks.
+    pub fn rollback(self) {}
+}
+
+impl Drop for PessimisticTransaction {
+    fn drop(&mut self) {
+        self.locks.unlock(self.id, &self.held);
+    }
+}
@@ -1,57 +1,548 @@
+//! Write-ahead log.
+//!
+//! Every write is appended as one checksummed record before it reaches the
+//! memtable:
+//!
+//! ```text
+//! [crc32 u32][len u32][type u8][payload: len bytes]
+//! ```
+//!
+//! The CRC covers `len`, `type` and the payload. Payloads are
+//!
+//! ```text
+//! put:          [seq u64][key_len u32][key][value]
+//! delete:       [seq u64][key]
+//! batch:        [seq u64][count u32] ([type u8][key_len u32][key][value_len u32][value]) * count
+//! expiring put: [seq u64][expires_at u64][key_len u32][key][value]
+//! merge:        [seq u64][key_len u32][key][operand]
+//! family batch: [seq u64][count u32] ([cf u32][type u8][key_len u32][key][value_len u32][value]) * count
+//! ```
+//!
+//! `seq` is the sequence number of the write; the entries of a batch take
+//! consecutive numbers starting from it. `expires_at` is a unix time in
+//! milliseconds. Batch entry types are 0 for a put, 1 for a delete, 2 for an
+//! expiring put, with its `expires_at u64` right after the type, and 3 for a
+//! merge. Writes to the default column family use the records above; any
+//! write touching another family is logged as a family batch, which tags
+//! each entry with the id of its family.
+//!
+//! The log is split into segments. Writes go to `wal.log`; when a Memtable
+//! fills up, `wal.log` is sealed by renaming it to `wal-<n>.log`, and the
+//! sealed segment is deleted once every write in it has been flushed.
+//! Replay reads the sealed segments in order, then `wal.log`.
+//!
+//! A crash mid-append can leave the end of `wal.log` torn: a record cut
+//! short, or one whose bytes never fully reached the disk, possibly followed
+//! by zeros the filesystem padded the file with. Such a tail is reported as
+//! [`ShortDBErrors::TornWALRecord`] and dropped on replay. A bad record with
+//! anything but zeros after it, or one that runs past the end of the file
+//! with an intact record somewhere in what it would cover, cannot be
+//! explained by a crash and is reported as [`ShortDBErrors::CorruptedWAL`],
+//! as is any bad record in a sealed segment, which was synced before it was
+//! sealed.
+//!
+//! An append that fails partway is cut off `wal.log` at once, so a write
+//! reported as failed never comes back and later records never follow a
+//! torn one. Should cutting it off fail too, the log takes no more appends.
+
+use super::block::ValueKind;
+use super::column_family::DEFAULT_ID;
+use crate::errors::{Result, ShortDBErrors};
 use bytes::Bytes;
-use std::fs::{File, OpenOptions};
-use std::io::{self, BufReader, Read, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 
+const HEADER_SIZE: usize = 9;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RecordType {
+    Put = 1,
+    Delete = 2,
+    Batch = 3,
+    ExpiringPut = 4,
+    Merge = 5,
+    FamilyBatch = 6,
+}
+
+const BATCH_PUT: u8 = 0;
+const BATCH_DELETE: u8 = 1;
+const BATCH_EXPIRING_PUT: u8 = 2;
+const BATCH_MERGE: u8 = 3;
+
+impl RecordType {
+    fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            1 => Some(RecordType::Put),
+            2 => Some(RecordType::Delete),
+            3 => Some(RecordType::Batch),
+            4 => Some(RecordType::ExpiringPut),
+            5 => Some(RecordType::Merge),
+            6 => Some(RecordType::FamilyBatch),
+            _ => None,
+        }
+    }
+}
+
 pub(crate) struct WALEntry {
+    /// Id of the column family written to.
+    pub(crate) cf: u32,
+    pub(crate) kind: ValueKind,
     pub(crate) key: Bytes,
     pub(crate) value: Bytes,
+    /// Unix time in milliseconds from which a put reads as absent.
+    pub(crate) expires_at: Option<u64>,
 }
 
+impl WALEntry {
+    pub(crate) fn put(key: &[u8], value: &[u8]) -> Self {
+        WALEntry {
+            cf: DEFAULT_ID,
+            kind: ValueKind::Put,
+            key: Bytes::copy_from_slice(key),
+            value: Bytes::copy_from_slice(value),
+            expires_at: None,
+        }
+    }
+
+    pub(crate) fn put_expiring(key: &[u8], value: &[u8], expires_at: u64) -> Self {
+        WALEntry {
+            expires_at: Some(expires_at),
+            ..WALEntry::put(key, value)
+        }
+    }
+
+    pub(crate) fn merge(key: &[u8], operand: &[u8]) -> Self {
+        WALEntry {
+            kind: ValueKind::Merge,
+            ..WALEntry::put(key, operand)
+        }
+    }
+
+    pub(crate) fn delete(key: &[u8]) -> Self {
+        WALEntry {
+            cf: DEFAULT_ID,
+            kind: ValueKind::Delete,
+            key: Bytes::copy_from_slice(key),
+            value: Bytes::new(),
+            expires_at: None,
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 pub(crate) struct WAL {
+    dir: PathBuf,
+    /// The segment writes are appended to.
     path: PathBuf,
     file: File,
+    /// Number the next sealed segment gets.
+    next_segment: u64,
+    /// Why a failed append could not be cut off again, if it could not.
+    /// Nothing may follow it, so every later append fails.
+    error: Option<String>,
 }
 
 impl WAL {
     pub(crate) fn new<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
-        let path = dir.as_ref().join("wal.log");
+        let dir = dir.as_ref().to_path_buf();
+        let path = dir.join("wal.log");
         let file = OpenOptions::new().create(true).append(true).open(&path)?;
-        Ok(WAL { path, file })
+        let mut wal = WAL {
+            dir,
+            path,
+            file,
+            next_segment: 1,
+            error: None,
+        };
+        if let Some((last, _)) = wal.sealed_segments()?.last() {
+            wal.next_segment = last + 1;
+        }
+        Ok(wal)
     }
 
-    pub(crate) fn write(&mut self, entry: &WALEntry) -> io::Result<()> {
-        self.file.write_all(&entry.key.len().to_le_bytes())?; // Key length
-        self.file.write_all(entry.key.as_ref())?; // Key
-        self.file.write_all(&entry.value.len().to_le_bytes())?; // Value length
-        self.file.write_all(entry.value.as_ref())?; // Value
-        self.file.flush()?; // Ensure data is written to disk
+    /// Sealed segments still on disk, oldest first.
+    fn sealed_segments(&self) -> io::Result<Vec<(u64, PathBuf)>> {
+        let mut segments = Vec::new();
+        for child in self.dir.read_dir()? {
+            let path = child?.path();
+            let number = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_prefix("wal-")?.strip_suffix(".log"))
+                .and_then(|n| n.parse::<u64>().ok());
+            if let Some(number) = number {
+                segments.push((number, path));
+            }
+        }
+        segments.sort();
+        Ok(segments)
+    }
+
+    /// Seals the segment written so far and starts a fresh one. Returns the
+    /// path of the sealed segment.
+    pub(crate) fn roll(&mut self) -> io::Result<PathBuf> {
+        let sealed = self.dir.join(format!("wal-{:06}.log", self.next_segment));
+        self.next_segment += 1;
+        self.file.sync_all()?;
+        fs::rename(&self.path, &sealed)?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        Ok(sealed)
+    }
+
+    /// Appends a group of writes, each one record given by its first
+    /// sequence number and entries, with a single `write_all`, and with
+    /// `sync` a single sync, so that concurrent writers share the cost.
+    ///
+    /// An append that fails is cut off again, so that none of its records
+    /// come back on replay and the next append follows the last good one.
+    /// If even that fails, this and every later append fail with
+    /// [`ShortDBErrors::BackgroundError`].
+    pub(crate) fn write_group(&mut self, writes: &[(u64, &[WALEntry])], sync: bool) -> Result<()> {
+        if let Some(e) = &self.error {
+            return Err(ShortDBErrors::BackgroundError(e.clone()));
+        }
+        let mut records = Vec::new();
+        for (seq, entries) in writes {
+            match entries {
+                [] => {}
+                [entry] => encode(entry, *seq, &mut records),
+                _ => encode_batch(entries, *seq, &mut records),
+            }
+        }
+        let len = self.file.metadata()?.len();
+        let appended = self
+            .file
+            .write_all(&records)
+            .and_then(|()| self.file.flush())
+            .and_then(|()| if sync { self.file.sync_data() } else { Ok(()) });
+        if let Err(e) = appended {
+            let cut = self.file.set_len(len).and_then(|()| self.file.sync_all());
+            if let Err(cut) = cut {
+                self.error = Some(format!("could not cut a failed WAL append off: {}", cut));
+            }
+            return Err(e.into());
+        }
         Ok(())
     }
 
-    pub(crate) fn read_entries(&self) -> io::Result<Vec<WALEntry>> {
-        let file = File::open(&self.path)?;
-        let mut reader = BufReader::new(file);
+    /// Reads back every complete record of every segment with its sequence
+    /// number, in the order they were written. A torn tail is cut off
+    /// `wal.log` so new writes follow the last good record.
+    pub(crate) fn read_entries(&mut self) -> Result<Vec<(u64, WALEntry)>> {
         let mut entries = Vec::new();
+        for (_, segment) in self.sealed_segments()? {
+            entries.extend(read_segment(&segment, true)?.0);
+        }
+        let (tail, intact) = read_segment(&self.path, false)?;
+        if intact < self.file.metadata()?.len() {
+            self.file.set_len(intact)?;
+            self.file.sync_all()?;
+        }
+        entries.extend(tail);
+        Ok(entries)
+    }
+
+    /// Whether replay found sealed segments left over from the last run.
+    pub(crate) fn has_sealed_segments(&self) -> io::Result<bool> {
+        Ok(!self.sealed_segments()?.is_empty())
+    }
+
+    /// Deletes every sealed segment, once what they hold is in SSTs or
+    /// `wal.log`.
+    pub(crate) fn remove_sealed_segments(&mut self) -> io::Result<()> {
+        for (_, segment) in self.sealed_segments()? {
+            fs::remove_file(segment)?;
+        }
+        Ok(())
+    }
+
+    /// Empties `wal.log` once everything in it has been flushed to an SST.
+    pub(crate) fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_all()
+    }
+
+    /// Atomically replaces `wal.log` with just `entries`, keeping their
+    /// sequence numbers.
+    pub(crate) fn rewrite(&mut self, entries: &[(u64, WALEntry)]) -> Result<()> {
+        let tmp = self.path.with_extension("log.tmp");
+        let mut wal = WAL {
+            dir: self.dir.clone(),
+            path: tmp.clone(),
+            file: File::create(&tmp)?,
+            next_segment: self.next_segment,
+            error: None,
+        };
+        let writes: Vec<_> = entries
+            .iter()
+            .map(|(seq, entry)| (*seq, std::slice::from_ref(entry)))
+            .collect();
+        wal.write_group(&writes, false)?;
+        wal.file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+}
+
+fn encode(entry: &WALEntry, seq: u64, out: &mut Vec<u8>) {
+    if entry.cf != DEFAULT_ID {
+        return encode_batch(std::slice::from_ref(entry), seq, out);
+    }
+    let mut payload = Vec::with_capacity(12 + entry.key.len() + entry.value.len());
+    payload.extend_from_slice(&seq.to_le_bytes());
+    let record_type = match (entry.kind, entry.expires_at) {
+        (ValueKind::Put, Some(expires_at)) => {
+            payload.extend_from_slice(&expires_at.to_le_bytes());
+            payload.extend_from_slice(&(entry.key.len() as u32).to_le_bytes());
+            payload.extend_from_slice(&entry.key);
+            payload.extend_from_slice(&entry.value);
+            RecordType::ExpiringPut
+        }
+        (ValueKind::Put, None) => {
+            payload.extend_from_slice(&(entry.key.len() as u32).to_le_bytes());
+            payload.extend_from_slice(&entry.key);
+            payload.extend_from_slice(&entry.value);
+            RecordType::Put
+        }
+        (ValueKind::Merge, _) => {
+            payload.extend_from_slice(&(entry.key.len() as u32).to_le_bytes());
+            payload.extend_from_slice(&entry.key);
+            payload.extend_from_slice(&entry.value);
+            RecordType::Merge
+        }
+        (ValueKind::Delete, _) => {
+            payload.extend_from_slice(&entry.key);
+            RecordType::Delete
+        }
+    };
+    encode_record(record_type, &payload, out)
+}
 
-        let mut buffer = vec![0; 8];
+/// Encodes `entries` as one batch record, recovered all or nothing. They
+/// take the sequence numbers from `first_seq` on.
+fn encode_batch(entries: &[WALEntry], first_seq: u64, out: &mut Vec<u8>) {
+    let families = entries.iter().any(|e| e.cf != DEFAULT_ID);
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&first_seq.to_le_bytes());
+    payload.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for entry in entries {
+        if families {
+            payload.extend_from_slice(&entry.cf.to_le_bytes());
+        }
+        match (entry.kind, entry.expires_at) {
+            (ValueKind::Put, Some(expires_at)) => {
+                payload.push(BATCH_EXPIRING_PUT);
+                payload.extend_from_slice(&expires_at.to_le_bytes());
+            }
+            (ValueKind::Put, None) => payload.push(BATCH_PUT),
+            (ValueKind::Delete, _) => payload.push(BATCH_DELETE),
+            (ValueKind::Merge, _) => payload.push(BATCH_MERGE),
+        }
+        payload.extend_from_slice(&(entry.key.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&entry.key);
+        payload.extend_from_slice(&(entry.value.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&entry.value);
+    }
+    let record_type = if families {
+        RecordType::FamilyBatch
+    } else {
+        RecordType::Batch
+    };
+    encode_record(record_type, &payload, out)
+}
 
-        while let Ok(_) = reader.read_exact(&mut buffer) {
-            let key_len = usize::from_le_bytes(buffer[0..8].try_into().unwrap());
-            let mut key = vec![0; key_len];
-            reader.read_exact(&mut key)?;
+/// Adds the header to `payload` and appends the record to `out`. Records
+/// reach the file whole, with the rest of their group, so a crash can only
+/// ever leave a prefix of the group behind.
+fn encode_record(record_type: RecordType, payload: &[u8], out: &mut Vec<u8>) {
+    let start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.push(record_type as u8);
+    out.extend_from_slice(payload);
+    let crc = crc32fast::hash(&out[start + 4..]);
+    out[start..start + 4].copy_from_slice(&crc.to_le_bytes());
+}
 
-            reader.read_exact(&mut buffer)?;
-            let value_len = usize::from_le_bytes(buffer[0..8].try_into().unwrap());
-            let mut value = vec![0; value_len];
-            reader.read_exact(&mut value)?;
+/// Decodes one segment. Returns its writes and how much of the file they
+/// take up; a torn tail after that is only allowed unless `sealed`.
+fn read_segment(path: &Path, sealed: bool) -> Result<(Vec<(u64, WALEntry)>, u64)> {
+    let data = fs::read(path)?;
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        match decode_record(&data, pos) {
+            Ok(next) => {
+                entries.extend(decode_payload(&data[pos..next], pos)?);
+                pos = next;
+            }
+            Err(ShortDBErrors::TornWALRecord { .. }) if sealed => {
+                return Err(corrupted(pos, "sealed segment ends in a bad record"));
+            }
+            Err(ShortDBErrors::TornWALRecord { offset }) => return Ok((entries, offset)),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((entries, data.len() as u64))
+}
+
+/// What is wrong with a bad record.
+enum Damage {
+    /// Its header or payload runs past the end of the file.
+    Short,
+    /// It fits, up to `end`, but fails its checksum.
+    Checksum { end: usize },
+}
+
+/// Checks the record starting at `pos` and returns where the next one starts.
+fn decode_record(data: &[u8], pos: usize) -> Result<usize> {
+    let (torn, reason) = match record_end(data, pos) {
+        Ok(end) => return Ok(end),
+        // Only the last append can be cut short by a crash; a length that
+        // reaches over intact records was damaged after the fact.
+        Err(Damage::Short) => (
+            !intact_record_after(data, pos),
+            "length runs over the records after it",
+        ),
+        // The file may have been extended, and zero-filled, before the
+        // record's bytes reached it.
+        Err(Damage::Checksum { end }) => (data[end..].iter().all(|&b| b == 0), "checksum mismatch"),
+    };
+    if torn {
+        Err(ShortDBErrors::TornWALRecord { offset: pos as u64 })
+    } else {
+        Err(corrupted(pos, reason))
+    }
+}
+
+fn record_end(data: &[u8], pos: usize) -> std::result::Result<usize, Damage> {
+    if data.len() - pos < HEADER_SIZE {
+        return Err(Damage::Short);
+    }
+    let crc = u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+    let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+    let end = pos + HEADER_SIZE + len;
+    if end > data.len() {
+        return Err(Damage::Short);
+    }
+    if crc32fast::hash(&data[pos + 4..end]) != crc {
+        return Err(Damage::Checksum { end });
+    }
+    Ok(end)
+}
+
+/// Whether a whole, valid record starts anywhere after `pos`.
+fn intact_record_after(data: &[u8], pos: usize) -> bool {
+    (pos + 1..data.len().saturating_sub(HEADER_SIZE - 1)).any(|start| {
+        RecordType::from_u8(data[start + 8]).is_some() && record_end(data, start).is_ok()
+    })
+}
 
-            entries.push(WALEntry {
-                key: Bytes::from(key),
-                value: Bytes::from(value),
-            });
+/// Decodes a record that already passed its checksum.
+fn decode_payload(record: &[u8], pos: usize) -> Result<Vec<(u64, WALEntry)>> {
+    let record_type = RecordType::from_u8(record[8])
+        .ok_or_else(|| corrupted(pos, &format!("unknown record type {}", record[8])))?;
+    let mut reader = PayloadReader::new(&record[HEADER_SIZE..], pos);
+    let seq = reader.u64()?;
+    match record_type {
+        RecordType::Put => {
+            let key = reader.prefixed()?;
+            let value = reader.rest();
+            Ok(vec![(seq, WALEntry::put(key, value))])
         }
+        RecordType::Delete => Ok(vec![(seq, WALEntry::delete(reader.rest()))]),
+        RecordType::ExpiringPut => {
+            let expires_at = reader.u64()?;
+            let key = reader.prefixed()?;
+            let value = reader.rest();
+            Ok(vec![(seq, WALEntry::put_expiring(key, value, expires_at))])
+        }
+        RecordType::Merge => {
+            let key = reader.prefixed()?;
+            let operand = reader.rest();
+            Ok(vec![(seq, WALEntry::merge(key, operand))])
+        }
+        RecordType::Batch | RecordType::FamilyBatch => {
+            let count = reader.u32()?;
+            let mut entries = Vec::with_capacity(count as usize);
+            for i in 0..count as u64 {
+                let cf = match record_type {
+                    RecordType::FamilyBatch => reader.u32()?,
+                    _ => DEFAULT_ID,
+                };
+                let kind = reader.u8()?;
+                let expires_at = match kind {
+                    BATCH_EXPIRING_PUT => Some(reader.u64()?),
+                    _ => None,
+                };
+                let key = reader.prefixed()?;
+                let value = reader.prefixed()?;
+                let entry = match (kind, expires_at) {
+                    (_, Some(expires_at)) => WALEntry::put_expiring(key, value, expires_at),
+                    (BATCH_PUT, None) => WALEntry::put(key, value),
+                    (BATCH_DELETE, None) => WALEntry::delete(key),
+                    (BATCH_MERGE, None) => WALEntry::merge(key, value),
+                    _ => return Err(corrupted(pos, "unknown batch entry kind")),
+                };
+                entries.push((seq + i, WALEntry { cf, ..entry }));
+            }
+            if !reader.rest().is_empty() {
+                return Err(corrupted(pos, "trailing bytes after batch"));
+            }
+            Ok(entries)
+        }
+    }
+}
 
-        Ok(entries)
+fn corrupted(pos: usize, reason: &str) -> ShortDBErrors {
+    ShortDBErrors::CorruptedWAL {
+        offset: pos as u64,
+        reason: reason.to_string(),
+    }
+}
+
+struct PayloadReader<'a> {
+    data: &'a [u8],
+    record: usize,
+}
+
+impl<'a> PayloadReader<'a> {
+    fn new(data: &'a [u8], record: usize) -> Self {
+        PayloadReader { data, record }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.data.len() < n {
+            return Err(corrupted(self.record, "payload too short"));
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn prefixed(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
     }
 }
@@ -20,7 +20,7 @@
 //! use shorterdb::kv::db::ShorterDB;
 //! use std::path::Path;
 //!
-//! let mut db = ShorterDB::new(Path::new("./test_db")).unwrap();
+//! let db = ShorterDB::new(Path::new("./test_db")).unwrap();
 //! db.set(b"key1", b"value1").unwrap();
 //! let value = db.get(b"key1").unwrap();
 //! assert_eq!(value, Some(b"value1".to_vec()));
@@ -29,4 +29,15 @@
 pub mod errors;
 pub mod kv;
 
+pub use kv::batch::WriteBatch;
+pub use kv::cache::{BlockCache, CacheStats};
+pub use kv::column_family::DEFAULT_COLUMN_FAMILY;
+pub use kv::commit::WriteStats;
+pub use kv::compression::Compressor;
 pub use kv::db::ShorterDB;
+pub use kv::iterator::DBIterator;
+pub use kv::merge::{AppendOperator, MaxOperator, MergeOperator, U64AddOperator};
+pub use kv::options::{CompactionStrategy, CompressionType, Options};
+pub use kv::snapshot::Snapshot;
+pub use kv::stall::{StallReason, StallStats};
+pub use kv::transaction::{OptimisticTransaction, PessimisticTransaction};
@@ -7,13 +7,10 @@
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-use kv::db::ShorterDB;
+use shorterdb::{errors, ShorterDB};
 use std::io::{self, Write};
 use std::path::Path;
 
-pub mod errors;
-pub mod kv;
-
 #[derive(Parser)]
 #[command(name = "shortdb")]
 #[command(about = "A simple key-value store REPL", long_about = None)]
@@ -30,7 +27,7 @@ enum Commands {
 }
 
 fn main() -> Result<()> {
-    let mut db = ShorterDB::new(Path::new("./test_db"))?;
+    let db = ShorterDB::new(Path::new("./test_db"))?;
 
     println!("Welcome to the ShortDB REPL!");
     println!("Syntax:- \n (i) set <key> <value> : maps <key> and <value> \n ");
@@ -73,7 +70,7 @@ fn main() -> Result<()> {
                     Err(e) => println!("Some error happened, {}", e),
                 };
             }
-            Some(Commands::Delete { key }) => match db.delete(&key.as_bytes()) {
+            Some(Commands::Delete { key }) => match db.delete(key.as_bytes()) {
                 Ok(()) => {
                     println!("Value for key: {} changed to tombstone", key);
                 }
@@ -9,19 +9,17 @@ use proto::basic_server::{Basic, BasicServer};
 use proto::{GetRequest, GetResponse, SetRequest, SetResponse};
 use std::path::Path;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 use tonic::transport::Server;
 
-pub mod errors;
-pub mod kv;
-use kv::db::ShorterDB;
+use shorterdb::ShorterDB;
 
+#[allow(dead_code)]
 mod proto {
     tonic::include_proto!("commands");
 }
 
 struct DbOperations {
-    db: Arc<Mutex<ShorterDB>>, // Add ShorterDB to the struct
+    db: Arc<ShorterDB>, // Shared by every request; ShorterDB synchronizes itself
 }
 
 #[tonic::async_trait]
@@ -32,8 +30,7 @@ impl Basic for DbOperations {
     ) -> Result<tonic::Response<GetResponse>, tonic::Status> {
         let key = request.get_ref().key.clone();
 
-        let db = self.db.lock().await;
-        match db.get(key.as_bytes()) {
+        match self.db.get(key.as_bytes()) {
             Ok(Some(value)) => match std::str::from_utf8(&value) {
                 Ok(string_value) => {
                     let response = GetResponse {
@@ -55,8 +52,7 @@ impl Basic for DbOperations {
         let key = request.get_ref().key.clone();
         let value = request.get_ref().value.clone();
 
-        let mut db = self.db.lock().await;
-        match db.set(key.as_bytes(), value.as_bytes()) {
+        match self.db.set(key.as_bytes(), value.as_bytes()) {
             Ok(_) => {
                 let response = SetResponse { success: true };
                 Ok(tonic::Response::new(response))
@@ -70,7 +66,7 @@ impl Basic for DbOperations {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let addr = "[::1]:50051".parse()?;
 
-    let db = Arc::new(Mutex::new(ShorterDB::new(Path::new("./test_db"))?));
+    let db = Arc::new(ShorterDB::new(Path::new("./test_db"))?);
 
     let db_operations = DbOperations { db };
 
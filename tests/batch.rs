@@ -0,0 +1,73 @@
+mod common;
+
+use common::fresh_dir;
+use shorterdb::{ShorterDB, WriteBatch};
+use std::fs::{self, OpenOptions};
+
+#[test]
+fn test_batch_applies_puts_and_deletes() {
+    let dir = fresh_dir("shorterdb_batch_apply");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"from", b"100").unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch
+        .put(b"to", b"100")
+        .delete(b"from")
+        .put(b"audit", b"moved");
+    assert_eq!(batch.len(), 3);
+    db.write(batch).unwrap();
+
+    assert_eq!(db.get(b"from").unwrap(), None);
+    assert_eq!(db.get(b"to").unwrap(), Some(b"100".to_vec()));
+    drop(db);
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.get(b"from").unwrap(), None);
+    assert_eq!(db.get(b"audit").unwrap(), Some(b"moved".to_vec()));
+}
+
+#[test]
+fn test_torn_batch_is_recovered_all_or_nothing() {
+    let dir = fresh_dir("shorterdb_batch_torn");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"before", b"kept").unwrap();
+    let mut batch = WriteBatch::new();
+    for i in 0..10 {
+        batch.put(format!("key{}", i).as_bytes(), b"batched");
+    }
+    db.write(batch).unwrap();
+    drop(db);
+
+    // Cut the batch record short, as a crash in the middle of the append would.
+    let wal = dir.join("wal.log");
+    let len = fs::metadata(&wal).unwrap().len();
+    OpenOptions::new()
+        .write(true)
+        .open(&wal)
+        .unwrap()
+        .set_len(len - 20)
+        .unwrap();
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.get(b"before").unwrap(), Some(b"kept".to_vec()));
+    for i in 0..10 {
+        assert!(db.get(format!("key{}", i).as_bytes()).is_err());
+    }
+}
+
+#[test]
+fn test_batch_larger_than_the_memtable() {
+    let dir = fresh_dir("shorterdb_batch_large");
+    let db = ShorterDB::new(&dir).unwrap();
+    let mut batch = WriteBatch::new();
+    for i in 0..1000 {
+        batch.put(format!("key{:04}", i).as_bytes(), b"value");
+    }
+    db.write(batch).unwrap();
+    db.write(WriteBatch::new()).unwrap();
+    drop(db);
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.iter().count(), 1000);
+}
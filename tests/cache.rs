@@ -0,0 +1,123 @@
+mod common;
+
+use common::fresh_dir;
+use shorterdb::{BlockCache, Options, ShorterDB};
+use std::sync::Arc;
+
+fn with_cache(cache: &Arc<BlockCache>) -> Options {
+    Options {
+        block_cache: Some(Arc::clone(cache)),
+        ..Default::default()
+    }
+}
+
+fn fill(db: &ShorterDB, tag: &str) {
+    for i in 0..1024 {
+        db.set(
+            format!("key{:05}", i).as_bytes(),
+            format!("{}{}", tag, i).as_bytes(),
+        )
+        .unwrap();
+    }
+}
+
+#[test]
+fn test_repeated_reads_hit_the_cache() {
+    let dir = fresh_dir("shorterdb_cache_hits");
+    let cache = Arc::new(BlockCache::new(1024 * 1024));
+    let db = ShorterDB::new_with_options(&dir, with_cache(&cache)).unwrap();
+    fill(&db, "value");
+
+    db.get(b"key00001").unwrap();
+    let before = cache.stats();
+    for _ in 0..10 {
+        assert_eq!(db.get(b"key00001").unwrap(), Some(b"value1".to_vec()));
+    }
+    let after = cache.stats();
+    assert_eq!(after.hits - before.hits, 10);
+    assert_eq!(after.misses, before.misses);
+    assert!(after.usage > 0);
+}
+
+#[test]
+fn test_databases_sharing_a_cache_see_their_own_blocks() {
+    let (dir_a, dir_b) = (
+        fresh_dir("shorterdb_cache_shared_a"),
+        fresh_dir("shorterdb_cache_shared_b"),
+    );
+    let cache = Arc::new(BlockCache::new(1024 * 1024));
+    let a = ShorterDB::new_with_options(&dir_a, with_cache(&cache)).unwrap();
+    let b = ShorterDB::new_with_options(&dir_b, with_cache(&cache)).unwrap();
+
+    // Both databases number their tables the same way.
+    fill(&a, "a");
+    fill(&b, "b");
+    for _ in 0..2 {
+        for i in 0..1024 {
+            let key = format!("key{:05}", i);
+            assert_eq!(
+                a.get(key.as_bytes()).unwrap(),
+                Some(format!("a{}", i).into_bytes())
+            );
+            assert_eq!(
+                b.get(key.as_bytes()).unwrap(),
+                Some(format!("b{}", i).into_bytes())
+            );
+        }
+    }
+    assert!(cache.stats().hits > 0);
+}
+
+#[test]
+fn test_cache_stays_within_its_capacity() {
+    let dir = fresh_dir("shorterdb_cache_capacity");
+    let cache = Arc::new(BlockCache::new(64 * 1024));
+    let db = ShorterDB::new_with_options(&dir, with_cache(&cache)).unwrap();
+    let value = vec![b'v'; 500];
+    for i in 0..4096 {
+        db.set(format!("key{:05}", i).as_bytes(), &value).unwrap();
+    }
+
+    for i in 0..4096 {
+        assert_eq!(
+            db.get(format!("key{:05}", i).as_bytes()).unwrap(),
+            Some(value.clone())
+        );
+    }
+    let stats = cache.stats();
+    assert!(stats.usage <= stats.capacity);
+    assert!(stats.misses > 0);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_cached_blocks_do_not_pin_deleted_tables() {
+    let dir = fresh_dir("shorterdb_cache_mmap");
+    let cache = Arc::new(BlockCache::new(16 * 1024 * 1024));
+    let options = Options {
+        mmap_reads: true,
+        ..with_cache(&cache)
+    };
+    let db = ShorterDB::new_with_options(&dir, options).unwrap();
+    fill(&db, "old");
+    db.wait_for_background_work().unwrap();
+    for i in 0..1024 {
+        db.get(format!("key{:05}", i).as_bytes()).unwrap();
+    }
+
+    // Compaction replaces every table the cached blocks came from.
+    for round in 0..4 {
+        fill(&db, &format!("new{}", round));
+    }
+    db.wait_for_background_work().unwrap();
+    assert!(cache.stats().usage > 0);
+
+    let dir = dir.to_str().unwrap().to_string();
+    let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+    let pinned: Vec<&str> = maps
+        .lines()
+        .filter(|line| line.contains(&dir) && line.ends_with("(deleted)"))
+        .collect();
+    assert!(pinned.is_empty(), "{:#?}", pinned);
+    assert_eq!(db.get(b"key00001").unwrap(), Some(b"new31".to_vec()));
+}
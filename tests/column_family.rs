@@ -0,0 +1,200 @@
+mod common;
+
+use common::fresh_dir;
+use shorterdb::errors::ShortDBErrors;
+use shorterdb::{Options, ShorterDB, U64AddOperator, WriteBatch};
+use std::fs;
+use std::sync::Arc;
+
+#[test]
+fn test_column_families_are_separate_keyspaces() {
+    let dir = fresh_dir("shorterdb_cf_keyspaces");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.create_column_family("users", Options::default())
+        .unwrap();
+    assert_eq!(db.column_families(), vec!["default", "users"]);
+
+    db.set(b"k", b"default value").unwrap();
+    db.set_cf("users", b"k", b"users value").unwrap();
+    db.set_cf("users", b"alice", b"1").unwrap();
+    assert_eq!(db.get(b"k").unwrap(), Some(b"default value".to_vec()));
+    assert_eq!(
+        db.get_cf("default", b"k").unwrap(),
+        Some(b"default value".to_vec())
+    );
+    assert_eq!(
+        db.get_cf("users", b"k").unwrap(),
+        Some(b"users value".to_vec())
+    );
+    assert!(matches!(db.get(b"alice"), Err(ShortDBErrors::KeyNotFound)));
+
+    let keys: Vec<Vec<u8>> = db.iter_cf("users").unwrap().map(|r| r.unwrap().0).collect();
+    assert_eq!(keys, vec![b"alice".to_vec(), b"k".to_vec()]);
+
+    db.delete_cf("users", b"k").unwrap();
+    assert_eq!(db.get_cf("users", b"k").unwrap(), None);
+    assert_eq!(db.get(b"k").unwrap(), Some(b"default value".to_vec()));
+
+    assert!(matches!(
+        db.set_cf("orders", b"k", b"v"),
+        Err(ShortDBErrors::UnknownColumnFamily(_))
+    ));
+    assert!(matches!(
+        db.create_column_family("users", Options::default()),
+        Err(ShortDBErrors::ColumnFamilyExists(_))
+    ));
+    assert!(matches!(
+        db.drop_column_family("default"),
+        Err(ShortDBErrors::InvalidColumnFamilyName(_))
+    ));
+
+    db.drop_column_family("users").unwrap();
+    assert_eq!(db.column_families(), vec!["default"]);
+    assert!(matches!(
+        db.get_cf("users", b"alice"),
+        Err(ShortDBErrors::UnknownColumnFamily(_))
+    ));
+    assert!(!dir.join("cf").join("1").exists());
+    drop(db);
+
+    // A dropped family need not be listed, and its logged writes are gone.
+    let db = ShorterDB::new(&dir).unwrap();
+    db.create_column_family("users", Options::default())
+        .unwrap();
+    assert!(matches!(
+        db.get_cf("users", b"alice"),
+        Err(ShortDBErrors::KeyNotFound)
+    ));
+}
+
+#[test]
+fn test_batch_across_families_survives_reopen() {
+    let dir = fresh_dir("shorterdb_cf_batch");
+    let families = || {
+        vec![
+            ("accounts", Options::default()),
+            ("audit", Options::default()),
+        ]
+    };
+    let db = ShorterDB::new_with_column_families(&dir, Options::default(), families()).unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch
+        .put_cf("accounts", b"alice", b"90")
+        .put_cf("accounts", b"bob", b"110")
+        .put_cf("audit", b"tx1", b"alice->bob 10")
+        .put(b"last_tx", b"tx1");
+    db.write(batch).unwrap();
+
+    // A batch naming an unknown family writes nothing.
+    let mut batch = WriteBatch::new();
+    batch
+        .put_cf("accounts", b"alice", b"0")
+        .put_cf("missing", b"k", b"v");
+    assert!(matches!(
+        db.write(batch),
+        Err(ShortDBErrors::UnknownColumnFamily(_))
+    ));
+    let snapshot = db.snapshot();
+    db.set_cf("accounts", b"alice", b"80").unwrap();
+    assert_eq!(
+        snapshot.get_cf(&db, "accounts", b"alice").unwrap(),
+        Some(b"90".to_vec())
+    );
+    drop(snapshot);
+    drop(db);
+
+    assert!(matches!(
+        ShorterDB::new(&dir),
+        Err(ShortDBErrors::UnopenedColumnFamily(_))
+    ));
+    let db = ShorterDB::new_with_column_families(&dir, Options::default(), families()).unwrap();
+    assert_eq!(
+        db.get_cf("accounts", b"alice").unwrap(),
+        Some(b"80".to_vec())
+    );
+    assert_eq!(
+        db.get_cf("accounts", b"bob").unwrap(),
+        Some(b"110".to_vec())
+    );
+    assert_eq!(
+        db.get_cf("audit", b"tx1").unwrap(),
+        Some(b"alice->bob 10".to_vec())
+    );
+    assert_eq!(db.get(b"last_tx").unwrap(), Some(b"tx1".to_vec()));
+}
+
+#[test]
+fn test_flushing_one_family_keeps_the_others_logged() {
+    let dir = fresh_dir("shorterdb_cf_flush");
+    let families = || vec![("busy", Options::default()), ("quiet", Options::default())];
+    let db = ShorterDB::new_with_column_families(&dir, Options::default(), families()).unwrap();
+    db.set_cf("quiet", b"rare", b"still here").unwrap();
+    for i in 0..1000 {
+        db.set_cf("busy", format!("key{:04}", i).as_bytes(), b"x")
+            .unwrap();
+    }
+    db.wait_for_background_work().unwrap();
+    let busy_l0 = dir.join("cf").join("1").join("l0");
+    assert!(busy_l0.read_dir().unwrap().next().is_some());
+    assert!(fs::metadata(dir.join("wal.log")).unwrap().len() > 0);
+    drop(db);
+
+    let db = ShorterDB::new_with_column_families(&dir, Options::default(), families()).unwrap();
+    assert_eq!(
+        db.get_cf("quiet", b"rare").unwrap(),
+        Some(b"still here".to_vec())
+    );
+    assert_eq!(db.iter_cf("busy").unwrap().count(), 1000);
+    assert_eq!(db.iter_cf("quiet").unwrap().count(), 1);
+}
+
+#[test]
+fn test_dropping_a_family_mid_flush_releases_the_segment() {
+    let dir = fresh_dir("shorterdb_cf_drop_flush");
+    let db = ShorterDB::new(&dir).unwrap();
+    for round in 0..8 {
+        db.create_column_family("doomed", Options::default())
+            .unwrap();
+        db.set_cf("doomed", b"k", b"v").unwrap();
+        // The 256th write fills the default Memtable and freezes both.
+        for i in 0..256 {
+            db.set(format!("key{}-{:03}", round, i).as_bytes(), b"x")
+                .unwrap();
+        }
+        db.drop_column_family("doomed").unwrap();
+    }
+    db.wait_for_background_work().unwrap();
+
+    let sealed: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("wal-"))
+        .collect();
+    assert!(sealed.is_empty(), "{:?}", sealed);
+    assert_eq!(db.iter().count(), 8 * 256);
+}
+
+#[test]
+fn test_families_have_their_own_options() {
+    let dir = fresh_dir("shorterdb_cf_options");
+    let counters = Options {
+        merge_operator: Some(Arc::new(U64AddOperator)),
+        ..Default::default()
+    };
+    let db =
+        ShorterDB::new_with_column_families(&dir, Options::default(), vec![("counters", counters)])
+            .unwrap();
+    db.merge_cf("counters", b"hits", &2u64.to_le_bytes())
+        .unwrap();
+    db.merge_cf("counters", b"hits", &3u64.to_le_bytes())
+        .unwrap();
+    assert_eq!(
+        db.get_cf("counters", b"hits").unwrap(),
+        Some(5u64.to_le_bytes().to_vec())
+    );
+    assert!(matches!(
+        db.merge(b"hits", &1u64.to_le_bytes()),
+        Err(ShortDBErrors::NoMergeOperator)
+    ));
+}
@@ -0,0 +1,29 @@
+//! Helpers shared by the integration tests.
+
+use std::ops::Deref;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// A directory of its own for one test, removed once dropped, whether the
+/// test passed or not.
+pub struct TestDir(TempDir);
+
+/// Creates an empty directory whose name starts with `name` and is unique
+/// to this run, so concurrent test runs never share one.
+pub fn fresh_dir(name: &str) -> TestDir {
+    TestDir(tempfile::Builder::new().prefix(name).tempdir().unwrap())
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        self.0.path()
+    }
+}
+
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        self.0.path()
+    }
+}
@@ -0,0 +1,275 @@
+mod common;
+
+use common::fresh_dir;
+use shorterdb::errors::ShortDBErrors;
+use shorterdb::{CompactionStrategy, Options, ShorterDB};
+use std::fs;
+use std::path::Path;
+
+fn tables_in(dir: &Path, level: usize) -> usize {
+    match fs::read_dir(dir.join(format!("l{}", level))) {
+        Ok(entries) => entries
+            .filter(|e| e.as_ref().unwrap().path().extension().unwrap() == "sst")
+            .count(),
+        Err(_) => 0,
+    }
+}
+
+#[test]
+fn test_level0_is_compacted_into_level1() {
+    let dir = fresh_dir("shorterdb_compaction_l0");
+    let db = ShorterDB::new(&dir).unwrap();
+
+    for round in 0..4 {
+        for i in 0..512 {
+            let key = format!("key{:05}", i);
+            db.set(key.as_bytes(), format!("value{}-{}", i, round).as_bytes())
+                .unwrap();
+        }
+    }
+
+    db.wait_for_background_work().unwrap();
+    assert!(tables_in(&dir, 0) < 4);
+    assert!(tables_in(&dir, 1) > 0);
+    for i in 0..512 {
+        let key = format!("key{:05}", i);
+        assert_eq!(
+            db.get(key.as_bytes()).unwrap(),
+            Some(format!("value{}-3", i).into_bytes())
+        );
+    }
+}
+
+#[test]
+fn test_deleted_data_is_reclaimed_at_the_bottom_level() {
+    let dir = fresh_dir("shorterdb_compaction_tombstones");
+    let db = ShorterDB::new(&dir).unwrap();
+
+    for i in 0..1024 {
+        db.set(format!("key{:05}", i).as_bytes(), b"value").unwrap();
+    }
+    db.wait_for_background_work().unwrap();
+    assert_eq!(tables_in(&dir, 0), 0);
+    assert_eq!(tables_in(&dir, 1), 1);
+
+    for i in 0..1024 {
+        db.delete(format!("key{:05}", i).as_bytes()).unwrap();
+    }
+
+    db.wait_for_background_work().unwrap();
+    // Every value and tombstone cancelled out, so nothing is left on disk.
+    assert_eq!(tables_in(&dir, 0), 0);
+    assert_eq!(tables_in(&dir, 1), 0);
+    for i in 0..1024 {
+        assert!(db.get(format!("key{:05}", i).as_bytes()).is_err());
+    }
+}
+
+#[test]
+fn test_compaction_cascades_to_deeper_levels() {
+    let dir = fresh_dir("shorterdb_compaction_cascade");
+    let db = ShorterDB::new(&dir).unwrap();
+    let value = vec![b'v'; 200];
+
+    for i in 0..12_000 {
+        db.set(format!("key{:06}", (i * 7919) % 12_000).as_bytes(), &value)
+            .unwrap();
+    }
+
+    db.wait_for_background_work().unwrap();
+    assert!(tables_in(&dir, 2) > 0);
+    for i in 0..12_000 {
+        assert_eq!(
+            db.get(format!("key{:06}", i).as_bytes()).unwrap(),
+            Some(value.clone())
+        );
+    }
+
+    drop(db);
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(
+        db.get(b"key000042").unwrap(),
+        Some(value.clone()),
+        "levels are found again after reopening"
+    );
+}
+
+fn universal() -> Options {
+    Options {
+        compaction_strategy: CompactionStrategy::Universal,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_universal_compaction_keeps_runs_in_level0() {
+    let dir = fresh_dir("shorterdb_compaction_universal");
+    let db = ShorterDB::new_with_options(&dir, universal()).unwrap();
+
+    for i in 0..8000 {
+        db.set(
+            format!("key{:05}", i % 3000).as_bytes(),
+            format!("value{}", i).as_bytes(),
+        )
+        .unwrap();
+    }
+    db.delete(b"key00007").unwrap();
+
+    db.wait_for_background_work().unwrap();
+    assert!(tables_in(&dir, 0) < 4);
+    assert_eq!(tables_in(&dir, 1), 0);
+    for i in 5000..8000 {
+        let key = format!("key{:05}", i % 3000);
+        let expected = if key == "key00007" {
+            None
+        } else {
+            Some(format!("value{}", i).into_bytes())
+        };
+        assert_eq!(db.get(key.as_bytes()).unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_universal_reads_see_the_latest_overwrite() {
+    let dir = fresh_dir("shorterdb_compaction_universal_reads");
+    let db = ShorterDB::new_with_options(&dir, universal()).unwrap();
+
+    // Flushes keep landing while merges of older runs are in flight.
+    for round in 0..40 {
+        let value = format!("round{}", round);
+        for i in 0..500 {
+            db.set(format!("key{:05}", i).as_bytes(), value.as_bytes())
+                .unwrap();
+        }
+        for i in 0..500 {
+            assert_eq!(
+                db.get(format!("key{:05}", i).as_bytes()).unwrap(),
+                Some(value.clone().into_bytes()),
+                "round {}",
+                round
+            );
+        }
+    }
+    drop(db);
+
+    let db = ShorterDB::new_with_options(&dir, universal()).unwrap();
+    for i in 0..500 {
+        assert_eq!(
+            db.get(format!("key{:05}", i).as_bytes()).unwrap(),
+            Some(b"round39".to_vec())
+        );
+    }
+}
+
+#[test]
+fn test_reopening_with_another_strategy_is_rejected() {
+    let dir = fresh_dir("shorterdb_compaction_mismatch");
+    let db = ShorterDB::new_with_options(&dir, universal()).unwrap();
+    db.set(b"key", b"value").unwrap();
+    drop(db);
+
+    assert!(matches!(
+        ShorterDB::new(&dir),
+        Err(ShortDBErrors::CompactionStrategyMismatch {
+            on_disk: CompactionStrategy::Universal,
+            requested: CompactionStrategy::Leveled,
+        })
+    ));
+    assert!(ShorterDB::new_with_options(&dir, universal()).is_ok());
+}
+
+#[test]
+fn test_options_file_without_a_strategy_is_rejected() {
+    let dir = fresh_dir("shorterdb_compaction_empty_options");
+    let db = ShorterDB::new_with_options(&dir, universal()).unwrap();
+    db.set(b"key", b"value").unwrap();
+    drop(db);
+
+    // What a crash right after creating the file used to leave behind.
+    fs::write(dir.join("OPTIONS"), b"").unwrap();
+    assert!(matches!(
+        ShorterDB::new(&dir),
+        Err(ShortDBErrors::InvalidOptionsFile(_))
+    ));
+}
+
+#[test]
+fn test_paused_compactions_wait_for_resume() {
+    let dir = fresh_dir("shorterdb_compaction_pause");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.pause_compactions();
+    for i in 0..2048 {
+        db.set(format!("key{:05}", i).as_bytes(), b"value").unwrap();
+    }
+    db.wait_for_background_work().unwrap();
+    assert_eq!(tables_in(&dir, 0), 8);
+    assert_eq!(tables_in(&dir, 1), 0);
+
+    db.resume_compactions();
+    db.wait_for_background_work().unwrap();
+    assert!(tables_in(&dir, 0) < 4);
+    assert!(tables_in(&dir, 1) > 0);
+    assert_eq!(db.get(b"key02047").unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn test_canceled_compactions_leave_nothing_behind() {
+    let dir = fresh_dir("shorterdb_compaction_cancel");
+    let db = ShorterDB::new(&dir).unwrap();
+    let value = vec![b'v'; 200];
+    for i in 0..6000 {
+        db.set(format!("key{:06}", (i * 7919) % 6000).as_bytes(), &value)
+            .unwrap();
+    }
+    // Whatever was running stops; only finished compactions are installed.
+    db.cancel_compactions();
+    db.wait_for_background_work().unwrap();
+    for level in fs::read_dir(&dir).unwrap() {
+        let level = level.unwrap().path();
+        if level.is_dir() {
+            for table in fs::read_dir(level).unwrap() {
+                let table = table.unwrap().path();
+                assert_eq!(table.extension().unwrap(), "sst", "{:?}", table);
+            }
+        }
+    }
+    for i in 0..6000 {
+        assert_eq!(
+            db.get(format!("key{:06}", i).as_bytes()).unwrap(),
+            Some(value.clone())
+        );
+    }
+
+    db.resume_compactions();
+    db.wait_for_background_work().unwrap();
+    assert!(tables_in(&dir, 0) < 4);
+    drop(db);
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.get(b"key001234").unwrap(), Some(value));
+}
+
+#[test]
+fn test_families_compact_in_parallel() {
+    let dir = fresh_dir("shorterdb_compaction_parallel");
+    let options = Options {
+        max_background_compactions: 4,
+        ..Default::default()
+    };
+    let names = ["a", "b", "c"];
+    let families = || names.iter().map(|n| (*n, Options::default())).collect();
+    let db = ShorterDB::new_with_column_families(&dir, options.clone(), families()).unwrap();
+    for i in 0..4000 {
+        for name in names {
+            let key = format!("key{:05}", (i * 7919) % 4000);
+            db.set_cf(name, key.as_bytes(), name.as_bytes()).unwrap();
+        }
+    }
+    db.wait_for_background_work().unwrap();
+    for name in names {
+        assert_eq!(db.iter_cf(name).unwrap().count(), 4000);
+    }
+    drop(db);
+
+    let db = ShorterDB::new_with_column_families(&dir, options, families()).unwrap();
+    assert_eq!(db.get_cf("b", b"key01234").unwrap(), Some(b"b".to_vec()));
+}
@@ -0,0 +1,203 @@
+mod common;
+
+use common::fresh_dir;
+use shorterdb::errors::{Result, ShortDBErrors};
+use shorterdb::{CompressionType, Compressor, Options, ShorterDB};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+fn with_compression(compression: CompressionType) -> Options {
+    Options {
+        compression,
+        ..Default::default()
+    }
+}
+
+fn json_value(i: usize) -> Vec<u8> {
+    format!(
+        r#"{{"id":{},"name":"user{}","email":"user{}@example.com","active":true,"tags":["a","b","c"]}}"#,
+        i, i, i
+    )
+    .into_bytes()
+}
+
+fn sst_bytes(dir: &Path) -> u64 {
+    let mut total = 0;
+    for level in fs::read_dir(dir).unwrap() {
+        let level = level.unwrap().path();
+        if level.is_dir() {
+            for table in fs::read_dir(level).unwrap() {
+                total += table.unwrap().metadata().unwrap().len();
+            }
+        }
+    }
+    total
+}
+
+fn write_and_check(name: &str, compression: CompressionType) -> u64 {
+    let dir = fresh_dir(name);
+    let db = ShorterDB::new_with_options(&dir, with_compression(compression)).unwrap();
+    for i in 0..2048 {
+        db.set(format!("key{:05}", i).as_bytes(), &json_value(i))
+            .unwrap();
+    }
+    drop(db);
+
+    let db = ShorterDB::new_with_options(&dir, with_compression(compression)).unwrap();
+    for i in 0..2048 {
+        assert_eq!(
+            db.get(format!("key{:05}", i).as_bytes()).unwrap(),
+            Some(json_value(i))
+        );
+    }
+    sst_bytes(&dir)
+}
+
+#[test]
+fn test_deflate_shrinks_tables() {
+    let plain = write_and_check("shorterdb_compression_none", CompressionType::None);
+    let deflated = write_and_check("shorterdb_compression_deflate", CompressionType::Deflate);
+    assert!(deflated * 2 < plain, "{} vs {}", deflated, plain);
+}
+
+#[cfg(feature = "lz4")]
+#[test]
+fn test_lz4_round_trips() {
+    write_and_check("shorterdb_compression_lz4", CompressionType::Lz4);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_zstd_round_trips() {
+    write_and_check("shorterdb_compression_zstd", CompressionType::Zstd);
+}
+
+#[cfg(feature = "snappy")]
+#[test]
+fn test_snappy_round_trips() {
+    write_and_check("shorterdb_compression_snappy", CompressionType::Snappy);
+}
+
+#[test]
+fn test_tables_stay_readable_after_switching_codecs() {
+    let dir = fresh_dir("shorterdb_compression_mixed");
+    let db = ShorterDB::new_with_options(&dir, with_compression(CompressionType::None)).unwrap();
+    for i in 0..600 {
+        db.set(format!("key{:05}", i).as_bytes(), &json_value(i))
+            .unwrap();
+    }
+    drop(db);
+
+    // Compaction now merges blocks of both codecs into deflated tables.
+    let db = ShorterDB::new_with_options(&dir, with_compression(CompressionType::Deflate)).unwrap();
+    for i in 300..1200 {
+        db.set(format!("key{:05}", i).as_bytes(), &json_value(i + 1))
+            .unwrap();
+    }
+    for i in 0..1200 {
+        let expected = if i < 300 { i } else { i + 1 };
+        assert_eq!(
+            db.get(format!("key{:05}", i).as_bytes()).unwrap(),
+            Some(json_value(expected))
+        );
+    }
+}
+
+/// Run-length encoding, as `(run length, byte)` pairs.
+struct RunLength;
+
+impl Compressor for RunLength {
+    fn name(&self) -> &str {
+        "run_length"
+    }
+
+    fn id(&self) -> u8 {
+        200
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        for run in data.chunk_by(|a, b| a == b) {
+            for part in run.chunks(255) {
+                out.extend_from_slice(&[part.len() as u8, part[0]]);
+            }
+        }
+        Ok(out)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if !data.len().is_multiple_of(2) {
+            return Err(ShortDBErrors::CorruptedSST("odd run length data".into()));
+        }
+        Ok(data
+            .chunks(2)
+            .flat_map(|pair| std::iter::repeat_n(pair[1], pair[0] as usize))
+            .collect())
+    }
+}
+
+fn with_run_length() -> Options {
+    Options {
+        compression: CompressionType::Custom(200),
+        compressors: vec![Arc::new(RunLength)],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_registered_codecs_compress_blocks() {
+    let dir = fresh_dir("shorterdb_compression_custom");
+    let value = vec![b'x'; 500];
+    let db = ShorterDB::new_with_options(&dir, with_run_length()).unwrap();
+    for i in 0..1024 {
+        db.set(format!("key{:05}", i).as_bytes(), &value).unwrap();
+    }
+    db.wait_for_background_work().unwrap();
+    assert!(sst_bytes(&dir) < 1024 * 500 / 4);
+    drop(db);
+
+    let db = ShorterDB::new_with_options(&dir, with_run_length()).unwrap();
+    assert_eq!(db.get(b"key00007").unwrap(), Some(value));
+    drop(db);
+
+    // Its blocks cannot be read once the codec is gone.
+    let db = ShorterDB::new(&dir).unwrap();
+    assert!(matches!(
+        db.get(b"key00007"),
+        Err(ShortDBErrors::UnsupportedCompression(200))
+    ));
+}
+
+#[test]
+fn test_codecs_with_reserved_or_unknown_ids_are_rejected() {
+    struct Reserved;
+    impl Compressor for Reserved {
+        fn name(&self) -> &str {
+            "reserved"
+        }
+        fn id(&self) -> u8 {
+            1
+        }
+        fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(data.to_vec())
+        }
+        fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(data.to_vec())
+        }
+    }
+
+    let dir = fresh_dir("shorterdb_compression_invalid");
+    let reserved = Options {
+        compressors: vec![Arc::new(Reserved)],
+        ..Default::default()
+    };
+    assert!(matches!(
+        ShorterDB::new_with_options(&dir, reserved),
+        Err(ShortDBErrors::InvalidOptions(_))
+    ));
+    assert!(matches!(
+        ShorterDB::new_with_options(&dir, with_compression(CompressionType::Custom(201))),
+        Err(ShortDBErrors::InvalidOptions(_))
+    ));
+}
@@ -0,0 +1,172 @@
+mod common;
+
+use common::fresh_dir;
+use shorterdb::errors::ShortDBErrors;
+use shorterdb::{Options, ShorterDB, WriteBatch};
+use std::sync::Arc;
+use std::thread;
+
+fn read_counter(db: &ShorterDB, key: &[u8]) -> Option<u64> {
+    match db.get(key) {
+        Ok(Some(value)) => Some(u64::from_le_bytes(value.try_into().unwrap())),
+        Ok(None) | Err(ShortDBErrors::KeyNotFound) => None,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+#[test]
+fn test_shorterdb_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<ShorterDB>();
+}
+
+#[test]
+fn test_writers_on_many_threads() {
+    let dir = fresh_dir("shorterdb_concurrency_writers");
+    let db = Arc::new(ShorterDB::new(&dir).unwrap());
+    let writers: Vec<_> = (0..8)
+        .map(|t| {
+            let db = Arc::clone(&db);
+            thread::spawn(move || {
+                for i in 0..300 {
+                    let key = format!("t{}:{:04}", t, i);
+                    db.set(key.as_bytes(), key.as_bytes()).unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    assert_eq!(db.iter().count(), 8 * 300);
+    assert_eq!(db.get(b"t7:0299").unwrap(), Some(b"t7:0299".to_vec()));
+    drop(db);
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.iter().count(), 8 * 300);
+}
+
+#[test]
+fn test_synced_writers_on_many_threads() {
+    let dir = fresh_dir("shorterdb_concurrency_synced");
+    let options = Options {
+        sync_writes: true,
+        ..Options::default()
+    };
+    let db = Arc::new(ShorterDB::new_with_options(&dir, options).unwrap());
+    let writers: Vec<_> = (0..8)
+        .map(|t| {
+            let db = Arc::clone(&db);
+            thread::spawn(move || {
+                for i in 0..50 {
+                    // Writes grouped with others still land as one record.
+                    let mut batch = WriteBatch::new();
+                    batch.put(format!("t{}:{:04}:a", t, i).as_bytes(), b"a");
+                    batch.put(format!("t{}:{:04}:b", t, i).as_bytes(), b"b");
+                    db.write(batch).unwrap();
+                    db.set(format!("t{}:{:04}:c", t, i).as_bytes(), b"c")
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    assert_eq!(db.iter().count(), 8 * 50 * 3);
+    // Writers that arrived together shared an append and its sync.
+    let stats = db.write_stats();
+    assert_eq!(stats.writes, 8 * 50 * 2);
+    assert_eq!(stats.syncs, stats.appends);
+    assert!(stats.syncs < stats.writes, "{:?}", stats);
+    drop(db);
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.iter().count(), 8 * 50 * 3);
+    assert_eq!(db.get(b"t7:0049:b").unwrap(), Some(b"b".to_vec()));
+}
+
+#[test]
+fn test_readers_run_alongside_conditional_writers() {
+    let dir = fresh_dir("shorterdb_concurrency_cas");
+    let db = Arc::new(ShorterDB::new(&dir).unwrap());
+    db.set(b"counter", &0u64.to_le_bytes()).unwrap();
+
+    // Every increment is a read followed by a compare-and-swap, retried
+    // until no other thread got in between.
+    let writers: Vec<_> = (0..4)
+        .map(|t| {
+            let db = Arc::clone(&db);
+            thread::spawn(move || {
+                for i in 0..200 {
+                    loop {
+                        let seen = read_counter(&db, b"counter").unwrap();
+                        let next = (seen + 1).to_le_bytes();
+                        if db
+                            .compare_and_swap(b"counter", Some(&seen.to_le_bytes()), Some(&next))
+                            .unwrap()
+                        {
+                            break;
+                        }
+                    }
+                    // Keep flushes coming while the counter is contended.
+                    db.set(format!("noise{}:{}", t, i).as_bytes(), b"x")
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    let reader = {
+        let db = Arc::clone(&db);
+        thread::spawn(move || {
+            let mut last = 0;
+            while last < 800 {
+                let seen = read_counter(&db, b"counter").unwrap();
+                assert!(seen >= last, "counter went back from {} to {}", last, seen);
+                last = seen;
+            }
+        })
+    };
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    reader.join().unwrap();
+    assert_eq!(read_counter(&db, b"counter"), Some(800));
+}
+
+#[test]
+fn test_optimistic_transactions_across_threads() {
+    let dir = fresh_dir("shorterdb_concurrency_optimistic");
+    let db = Arc::new(ShorterDB::new(&dir).unwrap());
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let db = Arc::clone(&db);
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    loop {
+                        let mut txn = db.begin_optimistic();
+                        let seen = txn
+                            .get(&db, b"balance")
+                            .or_else(|e| match e {
+                                ShortDBErrors::KeyNotFound => Ok(None),
+                                e => Err(e),
+                            })
+                            .unwrap()
+                            .map_or(0, |v| u64::from_le_bytes(v.try_into().unwrap()));
+                        txn.set(b"balance", &(seen + 1).to_le_bytes());
+                        match txn.commit(&db) {
+                            Ok(()) => break,
+                            Err(ShortDBErrors::Conflict) => continue,
+                            Err(e) => panic!("{}", e),
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    assert_eq!(read_counter(&db, b"balance"), Some(400));
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use common::fresh_dir;
+use shorterdb::ShorterDB;
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn test_compare_and_swap() {
+    let dir = fresh_dir("shorterdb_conditional_cas");
+    let db = ShorterDB::new(&dir).unwrap();
+
+    // `None` expects the key to be absent.
+    assert!(db
+        .compare_and_swap(b"leader", None, Some(b"node-a"))
+        .unwrap());
+    assert!(!db
+        .compare_and_swap(b"leader", None, Some(b"node-b"))
+        .unwrap());
+    assert!(!db
+        .compare_and_swap(b"leader", Some(b"node-b"), Some(b"node-c"))
+        .unwrap());
+    assert_eq!(db.get(b"leader").unwrap(), Some(b"node-a".to_vec()));
+
+    assert!(db
+        .compare_and_swap(b"leader", Some(b"node-a"), Some(b"node-b"))
+        .unwrap());
+    assert_eq!(db.get(b"leader").unwrap(), Some(b"node-b".to_vec()));
+
+    // `None` as the new value deletes the key.
+    assert!(db
+        .compare_and_swap(b"leader", Some(b"node-b"), None)
+        .unwrap());
+    assert_eq!(db.get(b"leader").unwrap(), None);
+    assert!(db
+        .compare_and_swap(b"leader", None, Some(b"node-c"))
+        .unwrap());
+
+    // An expired key counts as absent.
+    db.set(b"lease", b"node-a").unwrap();
+    db.expire_at(b"lease", SystemTime::now() - Duration::from_secs(1))
+        .unwrap();
+    assert!(db
+        .compare_and_swap(b"lease", None, Some(b"node-b"))
+        .unwrap());
+    assert_eq!(db.get(b"lease").unwrap(), Some(b"node-b".to_vec()));
+}
+
+#[test]
+fn test_put_if_absent_logs_only_successful_writes() {
+    let dir = fresh_dir("shorterdb_conditional_put_if_absent");
+    let db = ShorterDB::new(&dir).unwrap();
+    assert!(db.put_if_absent(b"request:1", b"first").unwrap());
+
+    let wal = dir.join("wal.log");
+    let len = fs::metadata(&wal).unwrap().len();
+    assert!(!db.put_if_absent(b"request:1", b"second").unwrap());
+    assert!(!db
+        .compare_and_swap(b"request:1", Some(b"stale"), Some(b"third"))
+        .unwrap());
+    assert_eq!(fs::metadata(&wal).unwrap().len(), len);
+    drop(db);
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.get(b"request:1").unwrap(), Some(b"first".to_vec()));
+}
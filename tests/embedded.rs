@@ -3,7 +3,7 @@ use std::path::Path;
 
 #[test]
 fn test_set_and_get() {
-    let mut db = ShorterDB::new(Path::new("./test_db")).unwrap();
+    let db = ShorterDB::new(Path::new("./test_db")).unwrap();
 
     db.set(b"key1", b"value1").unwrap();
 
@@ -13,7 +13,7 @@ fn test_set_and_get() {
 
 #[test]
 fn test_delete() {
-    let mut db = ShorterDB::new(Path::new("./test_db")).unwrap();
+    let db = ShorterDB::new(Path::new("./test_db")).unwrap();
 
     db.set(b"key2", b"value2").unwrap();
 
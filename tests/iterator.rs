@@ -0,0 +1,215 @@
+mod common;
+
+use common::fresh_dir;
+use shorterdb::ShorterDB;
+use std::collections::BTreeMap;
+
+/// Writes overwrites and deletes spread over the memtable and several
+/// levels, mirroring them in a BTreeMap.
+fn populate(db: &ShorterDB) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    let mut model = BTreeMap::new();
+    for i in 0..3000usize {
+        let key = format!("key{:05}", (i * 37) % 1500).into_bytes();
+        if i % 7 == 0 {
+            db.delete(&key).unwrap();
+            model.remove(&key);
+        } else {
+            let value = format!("value{}", i).into_bytes();
+            db.set(&key, &value).unwrap();
+            model.insert(key, value);
+        }
+    }
+    model
+}
+
+fn collect(iter: shorterdb::DBIterator) -> Vec<(Vec<u8>, Vec<u8>)> {
+    iter.map(|item| item.unwrap()).collect()
+}
+
+#[test]
+fn test_iter_matches_point_lookups() {
+    let dir = fresh_dir("shorterdb_iterator_full");
+    let db = ShorterDB::new(&dir).unwrap();
+    let model = populate(&db);
+
+    let expected: Vec<_> = model.clone().into_iter().collect();
+    assert_eq!(collect(db.iter()), expected);
+
+    drop(db);
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(collect(db.iter()), expected);
+}
+
+#[test]
+fn test_range_bounds() {
+    let dir = fresh_dir("shorterdb_iterator_range");
+    let db = ShorterDB::new(&dir).unwrap();
+    let model = populate(&db);
+    let (start, end) = (b"key00400".to_vec(), b"key00900".to_vec());
+
+    let expected: Vec<_> = model
+        .range(start.clone()..end.clone())
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    assert_eq!(collect(db.range(start.clone()..end.clone())), expected);
+
+    let expected: Vec<_> = model
+        .range(start.clone()..=end.clone())
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    assert_eq!(collect(db.range(start.clone()..=end.clone())), expected);
+
+    let expected: Vec<_> = model
+        .range(start.clone()..)
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    assert_eq!(collect(db.range(start..)), expected);
+
+    assert_eq!(collect(db.range("zzz".."zzzz")), vec![]);
+}
+
+#[test]
+fn test_paging_through_a_range() {
+    let dir = fresh_dir("shorterdb_iterator_paging");
+    let db = ShorterDB::new(&dir).unwrap();
+    for i in 0..1000 {
+        db.set(format!("session:{:04}", i).as_bytes(), b"active")
+            .unwrap();
+    }
+    db.set(b"user:1", b"not a session").unwrap();
+
+    let mut seen = Vec::new();
+    let mut after: Option<Vec<u8>> = None;
+    loop {
+        let page: Vec<_> = match &after {
+            Some(last) => {
+                use std::ops::Bound;
+                collect(db.range::<&[u8], _>((
+                    Bound::Excluded(last.as_slice()),
+                    Bound::Excluded(b"session;".as_slice()),
+                )))
+            }
+            None => collect(db.range("session:".."session;")),
+        };
+        let page: Vec<_> = page.into_iter().take(100).collect();
+        if page.is_empty() {
+            break;
+        }
+        after = Some(page.last().unwrap().0.clone());
+        seen.extend(page.into_iter().map(|(k, _)| k));
+    }
+    let expected: Vec<_> = (0..1000)
+        .map(|i| format!("session:{:04}", i).into_bytes())
+        .collect();
+    assert_eq!(seen, expected);
+}
+
+#[test]
+fn test_reverse_iteration() {
+    let dir = fresh_dir("shorterdb_iterator_reverse");
+    let db = ShorterDB::new(&dir).unwrap();
+    let model = populate(&db);
+
+    let expected: Vec<_> = model.clone().into_iter().rev().collect();
+    assert_eq!(collect_rev(db.iter()), expected);
+
+    let expected: Vec<_> = model
+        .range(b"key00100".to_vec()..=b"key00700".to_vec())
+        .rev()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    assert_eq!(collect_rev(db.range("key00100"..="key00700")), expected);
+}
+
+fn collect_rev(iter: shorterdb::DBIterator) -> Vec<(Vec<u8>, Vec<u8>)> {
+    iter.rev().map(|item| item.unwrap()).collect()
+}
+
+#[test]
+fn test_both_ends_meet_in_the_middle() {
+    let dir = fresh_dir("shorterdb_iterator_both_ends");
+    let db = ShorterDB::new(&dir).unwrap();
+    let model = populate(&db);
+
+    let mut iter = db.iter();
+    let (mut front, mut back) = (Vec::new(), Vec::new());
+    let mut forward = true;
+    loop {
+        let item = if forward {
+            iter.next()
+        } else {
+            iter.next_back()
+        };
+        match item {
+            Some(item) if forward => front.push(item.unwrap()),
+            Some(item) => back.push(item.unwrap()),
+            None => break,
+        }
+        forward = !forward;
+    }
+    assert!(iter.next().is_none());
+    assert!(iter.next_back().is_none());
+
+    front.extend(back.into_iter().rev());
+    assert_eq!(front, model.into_iter().collect::<Vec<_>>());
+}
+
+#[test]
+fn test_scan_prefix_and_latest_entries() {
+    let dir = fresh_dir("shorterdb_iterator_prefix");
+    let db = ShorterDB::new(&dir).unwrap();
+    for user in ["alice", "bob", "carol"] {
+        for i in 0..400 {
+            db.set(format!("{}/event{:04}", user, i).as_bytes(), b"x")
+                .unwrap();
+        }
+    }
+    db.delete(b"bob/event0399").unwrap();
+    db.set(&[0xff, 0xff], b"max").unwrap();
+    db.set(&[0xff, 0xff, 0x01], b"after max").unwrap();
+
+    let bob: Vec<_> = collect(db.scan_prefix(b"bob/"));
+    assert_eq!(bob.len(), 399);
+    assert!(bob.iter().all(|(k, _)| k.starts_with(b"bob/")));
+
+    let latest: Vec<_> = db
+        .scan_prefix(b"bob/")
+        .rev()
+        .take(3)
+        .map(|item| item.unwrap().0)
+        .collect();
+    assert_eq!(
+        latest,
+        vec![
+            b"bob/event0398".to_vec(),
+            b"bob/event0397".to_vec(),
+            b"bob/event0396".to_vec()
+        ]
+    );
+
+    let keys: Vec<_> = collect(db.scan_prefix(&[0xff]))
+        .into_iter()
+        .map(|(k, _)| k)
+        .collect();
+    assert_eq!(keys, vec![vec![0xff, 0xff], vec![0xff, 0xff, 0x01]]);
+}
+
+#[test]
+fn test_seek_for_prev() {
+    let dir = fresh_dir("shorterdb_iterator_seek_for_prev");
+    let db = ShorterDB::new(&dir).unwrap();
+    for i in 0..1000 {
+        db.set(format!("key{:05}", i * 10).as_bytes(), b"x")
+            .unwrap();
+    }
+
+    let first = |key: &str| {
+        db.seek_for_prev(key.as_bytes())
+            .next()
+            .map(|item| String::from_utf8(item.unwrap().0).unwrap())
+    };
+    assert_eq!(first("key00500").as_deref(), Some("key00500"));
+    assert_eq!(first("key00505").as_deref(), Some("key00500"));
+    assert_eq!(first("zzz").as_deref(), Some("key09990"));
+    assert_eq!(first("a"), None);
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use common::fresh_dir;
+use shorterdb::errors::ShortDBErrors;
+use shorterdb::ShorterDB;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+fn fill(db: &ShorterDB, value: &[u8]) {
+    for i in 0..256 {
+        db.set(format!("key{:03}", i).as_bytes(), value).unwrap();
+    }
+}
+
+fn current_manifest(dir: &Path) -> PathBuf {
+    dir.join(fs::read_to_string(dir.join("CURRENT")).unwrap().trim())
+}
+
+#[test]
+fn test_tables_unknown_to_the_manifest_are_not_loaded() {
+    let dir = fresh_dir("shorterdb_manifest_orphan");
+    let db = ShorterDB::new(&dir).unwrap();
+    fill(&db, b"old");
+    db.wait_for_background_work().unwrap();
+    let flushed = fs::read_dir(dir.join("l0"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    fill(&db, b"new");
+    drop(db);
+
+    // A leftover with a higher file number would win a directory scan.
+    let stray = dir.join("l0").join("000999.sst");
+    fs::copy(&flushed, &stray).unwrap();
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.get(b"key007").unwrap(), Some(b"new".to_vec()));
+    assert!(!stray.exists());
+}
+
+#[test]
+fn test_levels_survive_reopen_through_the_manifest() {
+    let dir = fresh_dir("shorterdb_manifest_reopen");
+    let db = ShorterDB::new(&dir).unwrap();
+    for round in 0..6 {
+        fill(&db, format!("round{}", round).as_bytes());
+    }
+    drop(db);
+    assert!(current_manifest(&dir).exists());
+
+    let db = ShorterDB::new(&dir).unwrap();
+    for i in 0..256 {
+        assert_eq!(
+            db.get(format!("key{:03}", i).as_bytes()).unwrap(),
+            Some(b"round5".to_vec())
+        );
+    }
+}
+
+#[test]
+fn test_torn_manifest_tail_is_ignored() {
+    let dir = fresh_dir("shorterdb_manifest_torn");
+    let db = ShorterDB::new(&dir).unwrap();
+    fill(&db, b"value");
+    drop(db);
+
+    // A record header promising more bytes than were ever written.
+    let mut manifest = OpenOptions::new()
+        .append(true)
+        .open(current_manifest(&dir))
+        .unwrap();
+    manifest.write_all(&100u32.to_le_bytes()).unwrap();
+    manifest.write_all(b"abc").unwrap();
+    drop(manifest);
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.get(b"key001").unwrap(), Some(b"value".to_vec()));
+    fill(&db, b"again");
+    drop(db);
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.get(b"key001").unwrap(), Some(b"again".to_vec()));
+}
+
+#[test]
+fn test_zeroed_or_garbage_manifest_tail_is_cut_off() {
+    let tails: [&[u8]; 3] = [
+        &[0; 64],
+        &[
+            0x5a, 0x01, 0xff, 0x37, 0x09, 0x00, 0x00, 0x00, 1, 2, 3, 4, 5, 6, 7, 8, 9,
+        ],
+        // A length of zero with a checksum that does not match it.
+        &[1, 2, 3, 4, 0, 0, 0, 0],
+    ];
+    for (i, tail) in tails.iter().enumerate() {
+        let dir = fresh_dir(&format!("shorterdb_manifest_tail{}", i));
+        let db = ShorterDB::new(&dir).unwrap();
+        fill(&db, b"value");
+        db.wait_for_background_work().unwrap();
+        drop(db);
+
+        let path = current_manifest(&dir);
+        let intact = fs::metadata(&path).unwrap().len();
+        let mut manifest = OpenOptions::new().append(true).open(&path).unwrap();
+        manifest.write_all(tail).unwrap();
+        drop(manifest);
+
+        let db = ShorterDB::new(&dir).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+        assert_eq!(db.get(b"key001").unwrap(), Some(b"value".to_vec()));
+        fill(&db, b"again");
+        db.wait_for_background_work().unwrap();
+        drop(db);
+        let db = ShorterDB::new(&dir).unwrap();
+        assert_eq!(db.get(b"key001").unwrap(), Some(b"again".to_vec()));
+    }
+}
+
+#[test]
+fn test_damage_before_intact_manifest_records_is_reported() {
+    let dir = fresh_dir("shorterdb_manifest_damaged");
+    let db = ShorterDB::new(&dir).unwrap();
+    fill(&db, b"value");
+    db.wait_for_background_work().unwrap();
+    fill(&db, b"again");
+    db.wait_for_background_work().unwrap();
+    drop(db);
+
+    // Flip a byte of the first record; the later ones still check out.
+    let path = current_manifest(&dir);
+    let mut data = fs::read(&path).unwrap();
+    data[10] ^= 0xff;
+    fs::write(&path, data).unwrap();
+
+    assert!(matches!(
+        ShorterDB::new(&dir),
+        Err(ShortDBErrors::CorruptedManifest(_))
+    ));
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use common::fresh_dir;
+use shorterdb::errors::ShortDBErrors;
+use shorterdb::{
+    AppendOperator, MaxOperator, MergeOperator, Options, ShorterDB, U64AddOperator, WriteBatch,
+};
+use std::path::Path;
+use std::sync::Arc;
+
+fn open_with(dir: &Path, operator: Arc<dyn MergeOperator>) -> ShorterDB {
+    let options = Options {
+        merge_operator: Some(operator),
+        ..Default::default()
+    };
+    ShorterDB::new_with_options(dir, options).unwrap()
+}
+
+fn counter(value: Option<Vec<u8>>) -> u64 {
+    u64::from_le_bytes(value.unwrap().try_into().unwrap())
+}
+
+#[test]
+fn test_u64_add_counts_without_reads() {
+    let dir = fresh_dir("shorterdb_merge_u64_add");
+    let db = open_with(&dir, Arc::new(U64AddOperator));
+
+    for _ in 0..5 {
+        db.merge(b"hits", &1u64.to_le_bytes()).unwrap();
+    }
+    assert_eq!(counter(db.get(b"hits").unwrap()), 5);
+
+    db.set(b"hits", &100u64.to_le_bytes()).unwrap();
+    db.merge(b"hits", &7u64.to_le_bytes()).unwrap();
+    assert_eq!(counter(db.get(b"hits").unwrap()), 107);
+
+    // A delete resets the base.
+    db.delete(b"hits").unwrap();
+    db.merge(b"hits", &2u64.to_le_bytes()).unwrap();
+    assert_eq!(counter(db.get(b"hits").unwrap()), 2);
+
+    let mut batch = WriteBatch::new();
+    batch
+        .merge(b"hits", &3u64.to_le_bytes())
+        .merge(b"misses", &1u64.to_le_bytes());
+    db.write(batch).unwrap();
+    assert_eq!(counter(db.get(b"hits").unwrap()), 5);
+    assert_eq!(counter(db.get(b"misses").unwrap()), 1);
+
+    // Operands that do not decode fail the read instead of guessing.
+    db.merge(b"hits", b"not a u64").unwrap();
+    assert!(matches!(
+        db.get(b"hits"),
+        Err(ShortDBErrors::MergeFailed(_))
+    ));
+}
+
+#[test]
+fn test_append_survives_flush_compaction_and_reopen() {
+    let dir = fresh_dir("shorterdb_merge_append");
+    let db = open_with(&dir, Arc::new(AppendOperator::with_delimiter(b",")));
+    db.set(b"log", b"start").unwrap();
+    let mut snapshot = None;
+    for round in 0..6 {
+        db.merge(b"log", format!("r{}", round).as_bytes()).unwrap();
+        if round == 2 {
+            snapshot = Some(db.snapshot());
+        }
+        // Push the operands through flushes and compactions.
+        for i in 0..400 {
+            db.set(format!("filler{:05}", i).as_bytes(), b"x").unwrap();
+        }
+    }
+    db.wait_for_background_work().unwrap();
+    assert!(dir.join("l1").read_dir().unwrap().next().is_some());
+
+    let expected = b"start,r0,r1,r2,r3,r4,r5".to_vec();
+    assert_eq!(db.get(b"log").unwrap(), Some(expected.clone()));
+    let snapshot = snapshot.unwrap();
+    assert_eq!(
+        snapshot.get(&db, b"log").unwrap(),
+        Some(b"start,r0,r1,r2".to_vec())
+    );
+    let scanned = db.scan_prefix(b"log").next().unwrap().unwrap();
+    assert_eq!(scanned, (b"log".to_vec(), expected.clone()));
+    let reversed = db.range("a".."m").next_back().unwrap().unwrap();
+    assert_eq!(reversed, (b"log".to_vec(), expected.clone()));
+    drop(snapshot);
+    drop(db);
+
+    let db = open_with(&dir, Arc::new(AppendOperator::with_delimiter(b",")));
+    db.merge(b"log", b"after").unwrap();
+    assert_eq!(
+        db.get(b"log").unwrap(),
+        Some(b"start,r0,r1,r2,r3,r4,r5,after".to_vec())
+    );
+}
+
+#[test]
+fn test_max_operator() {
+    let dir = fresh_dir("shorterdb_merge_max");
+    let db = open_with(&dir, Arc::new(MaxOperator));
+    for score in [3u32, 9, 4] {
+        db.merge(b"high", &score.to_be_bytes()).unwrap();
+    }
+    assert_eq!(db.get(b"high").unwrap(), Some(9u32.to_be_bytes().to_vec()));
+}
+
+#[test]
+fn test_merge_needs_an_operator() {
+    let dir = fresh_dir("shorterdb_merge_no_operator");
+    let db = ShorterDB::new(&dir).unwrap();
+    assert!(matches!(
+        db.merge(b"k", b"v"),
+        Err(ShortDBErrors::NoMergeOperator)
+    ));
+    drop(db);
+
+    let db = open_with(&dir, Arc::new(AppendOperator::new()));
+    db.merge(b"k", b"v").unwrap();
+    drop(db);
+
+    // Operands written earlier cannot be read without an operator.
+    let db = ShorterDB::new(&dir).unwrap();
+    assert!(matches!(db.get(b"k"), Err(ShortDBErrors::NoMergeOperator)));
+}
+
+#[test]
+fn test_batch_merges_need_an_operator() {
+    let dir = fresh_dir("shorterdb_merge_batch_no_operator");
+    let db = ShorterDB::new(&dir).unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put(b"a", b"1");
+    batch.merge(b"k", b"v");
+    assert!(matches!(
+        db.write(batch),
+        Err(ShortDBErrors::NoMergeOperator)
+    ));
+    // Nothing of the batch was written.
+    assert!(matches!(db.get(b"a"), Err(ShortDBErrors::KeyNotFound)));
+    assert!(matches!(db.get(b"k"), Err(ShortDBErrors::KeyNotFound)));
+}
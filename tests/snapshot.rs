@@ -0,0 +1,140 @@
+mod common;
+
+use common::fresh_dir;
+use shorterdb::{ShorterDB, WriteBatch};
+
+/// Writes enough filler to flush the memtable several times over, which
+/// also drives level 0 into compaction.
+fn churn(db: &ShorterDB, round: usize) {
+    for i in 0..2000 {
+        db.set(
+            format!("filler{:05}", i).as_bytes(),
+            format!("{}", round).as_bytes(),
+        )
+        .unwrap();
+    }
+}
+
+#[test]
+fn test_snapshot_ignores_later_writes() {
+    let dir = fresh_dir("shorterdb_snapshot_writes");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"a", b"1").unwrap();
+    db.set(b"b", b"1").unwrap();
+
+    let snap = db.snapshot();
+    db.set(b"a", b"2").unwrap();
+    db.delete(b"b").unwrap();
+    db.set(b"c", b"2").unwrap();
+
+    assert_eq!(snap.get(&db, b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(snap.get(&db, b"b").unwrap(), Some(b"1".to_vec()));
+    assert!(snap.get(&db, b"c").is_err());
+    assert_eq!(db.get(b"a").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(db.get(b"b").unwrap(), None);
+    assert!(db.snapshot().sequence() > snap.sequence());
+}
+
+#[test]
+fn test_snapshot_iterators_see_a_consistent_view() {
+    let dir = fresh_dir("shorterdb_snapshot_iter");
+    let db = ShorterDB::new(&dir).unwrap();
+    for key in ["k1", "k2", "k3", "p1"] {
+        db.set(key.as_bytes(), b"old").unwrap();
+    }
+    let snap = db.snapshot();
+
+    let mut batch = WriteBatch::new();
+    batch.put(b"k1", b"new").delete(b"k2").put(b"k4", b"new");
+    db.write(batch).unwrap();
+
+    let seen: Vec<(Vec<u8>, Vec<u8>)> = snap.iter(&db).map(|r| r.unwrap()).collect();
+    let expected: Vec<(Vec<u8>, Vec<u8>)> = ["k1", "k2", "k3", "p1"]
+        .iter()
+        .map(|k| (k.as_bytes().to_vec(), b"old".to_vec()))
+        .collect();
+    assert_eq!(seen, expected);
+
+    let reversed: Vec<Vec<u8>> = snap
+        .scan_prefix(&db, b"k")
+        .rev()
+        .map(|r| r.unwrap().0)
+        .collect();
+    assert_eq!(
+        reversed,
+        vec![b"k3".to_vec(), b"k2".to_vec(), b"k1".to_vec()]
+    );
+
+    let current: Vec<Vec<u8>> = db.range("k1".."k9").map(|r| r.unwrap().1).collect();
+    assert_eq!(
+        current,
+        vec![b"new".to_vec(), b"old".to_vec(), b"new".to_vec()]
+    );
+}
+
+#[test]
+fn test_snapshot_survives_flush_and_compaction() {
+    let dir = fresh_dir("shorterdb_snapshot_compaction");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"key", b"v1").unwrap();
+    db.set(b"gone", b"v1").unwrap();
+    let first = db.snapshot();
+    churn(&db, 1);
+
+    db.set(b"key", b"v2").unwrap();
+    db.delete(b"gone").unwrap();
+    let second = first.clone();
+    let third = db.snapshot();
+    churn(&db, 2);
+    db.set(b"key", b"v3").unwrap();
+    churn(&db, 3);
+    db.wait_for_background_work().unwrap();
+    assert!(dir.join("l1").read_dir().unwrap().next().is_some());
+
+    assert_eq!(first.get(&db, b"key").unwrap(), Some(b"v1".to_vec()));
+    assert_eq!(second.get(&db, b"gone").unwrap(), Some(b"v1".to_vec()));
+    assert_eq!(third.get(&db, b"key").unwrap(), Some(b"v2".to_vec()));
+    assert_eq!(third.get(&db, b"gone").unwrap(), None);
+    assert_eq!(db.get(b"key").unwrap(), Some(b"v3".to_vec()));
+    assert!(first
+        .iter(&db)
+        .all(|r| !r.unwrap().0.starts_with(b"filler")));
+    assert_eq!(
+        third
+            .range(&db, "filler00000"..="filler00000")
+            .next()
+            .unwrap()
+            .unwrap()
+            .1,
+        b"1".to_vec()
+    );
+
+    // Dropping every handle lets compaction reclaim the old versions.
+    drop(first);
+    drop(second);
+    drop(third);
+    churn(&db, 4);
+    assert_eq!(db.get(b"key").unwrap(), Some(b"v3".to_vec()));
+    assert_eq!(
+        db.snapshot().get(&db, b"filler00000").unwrap(),
+        Some(b"4".to_vec())
+    );
+}
+
+#[test]
+fn test_sequence_numbers_survive_reopen() {
+    let dir = fresh_dir("shorterdb_snapshot_reopen");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"key", b"flushed").unwrap();
+    churn(&db, 1);
+    db.set(b"tail", b"logged").unwrap();
+    let before = db.snapshot().sequence();
+    drop(db);
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.snapshot().sequence(), before);
+    db.set(b"key", b"rewritten").unwrap();
+    churn(&db, 2);
+    assert_eq!(db.get(b"key").unwrap(), Some(b"rewritten".to_vec()));
+    assert_eq!(db.get(b"tail").unwrap(), Some(b"logged".to_vec()));
+}
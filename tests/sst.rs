@@ -0,0 +1,140 @@
+mod common;
+
+use common::fresh_dir;
+use shorterdb::{Options, ShorterDB};
+use std::fs;
+use std::path::Path;
+
+fn sst_files(dir: &Path) -> usize {
+    fs::read_dir(dir.join("l0"))
+        .unwrap()
+        .filter(|e| e.as_ref().unwrap().path().extension().unwrap() == "sst")
+        .count()
+}
+
+#[test]
+fn test_flush_writes_one_table_per_memtable() {
+    let dir = fresh_dir("shorterdb_sst_flush");
+    let db = ShorterDB::new(&dir).unwrap();
+
+    for i in 0..1000 {
+        let key = format!("key{:05}", i);
+        db.set(key.as_bytes(), format!("value{}", i).as_bytes())
+            .unwrap();
+    }
+
+    db.wait_for_background_work().unwrap();
+    // 1000 writes with a 256 entry memtable means three flushes.
+    assert_eq!(sst_files(&dir), 3);
+    for i in 0..1000 {
+        let key = format!("key{:05}", i);
+        assert_eq!(
+            db.get(key.as_bytes()).unwrap(),
+            Some(format!("value{}", i).into_bytes())
+        );
+    }
+}
+
+#[test]
+fn test_newer_tables_shadow_older_ones() {
+    let dir = fresh_dir("shorterdb_sst_shadow");
+    let db = ShorterDB::new(&dir).unwrap();
+
+    for i in 0..256 {
+        db.set(format!("key{}", i).as_bytes(), b"old").unwrap();
+    }
+    db.set(b"key1", b"new").unwrap();
+    db.delete(b"key2").unwrap();
+    for i in 0..254 {
+        db.set(format!("filler{}", i).as_bytes(), b"x").unwrap();
+    }
+
+    db.wait_for_background_work().unwrap();
+    assert_eq!(sst_files(&dir), 2);
+    assert_eq!(db.get(b"key1").unwrap(), Some(b"new".to_vec()));
+    assert_eq!(db.get(b"key2").unwrap(), None);
+    assert_eq!(db.get(b"key3").unwrap(), Some(b"old".to_vec()));
+    assert!(db.get(b"missing").is_err());
+
+    // Tables are found again by a fresh handle on the same directory.
+    drop(db);
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.get(b"key1").unwrap(), Some(b"new".to_vec()));
+    assert_eq!(db.get(b"key3").unwrap(), Some(b"old".to_vec()));
+}
+
+#[test]
+fn test_queued_memtables_stay_readable() {
+    let dir = fresh_dir("shorterdb_sst_queued");
+    let db = ShorterDB::new(&dir).unwrap();
+
+    // Read every key right after writing it, whether its memtable is
+    // still live, waiting for the flush thread or already in a table.
+    for i in 0..2000 {
+        let key = format!("key{:05}", i);
+        db.set(key.as_bytes(), b"first").unwrap();
+        db.set(format!("key{:05}", i / 2).as_bytes(), b"second")
+            .unwrap();
+        assert!(db.get(key.as_bytes()).unwrap().is_some());
+        assert_eq!(
+            db.get(format!("key{:05}", i / 2).as_bytes()).unwrap(),
+            Some(b"second".to_vec())
+        );
+    }
+    assert_eq!(db.iter().count(), 2000);
+    db.wait_for_background_work().unwrap();
+    assert_eq!(db.iter().count(), 2000);
+    assert_eq!(db.get(b"key01999").unwrap(), Some(b"first".to_vec()));
+}
+
+#[test]
+fn test_negative_lookups_across_tables() {
+    let dir = fresh_dir("shorterdb_sst_filter");
+    let db = ShorterDB::new(&dir).unwrap();
+
+    for i in 0..2048 {
+        db.set(format!("key{:05}", i * 2).as_bytes(), b"present")
+            .unwrap();
+    }
+
+    // Odd keys fall inside every table's key range but were never written.
+    for i in 0..2048 {
+        assert!(matches!(
+            db.get(format!("key{:05}", i * 2 + 1).as_bytes()),
+            Err(shorterdb::errors::ShortDBErrors::KeyNotFound)
+        ));
+        assert_eq!(
+            db.get(format!("key{:05}", i * 2).as_bytes()).unwrap(),
+            Some(b"present".to_vec())
+        );
+    }
+}
+
+#[test]
+fn test_reads_through_memory_mapped_tables() {
+    let dir = fresh_dir("shorterdb_sst_mmap");
+    let options = Options {
+        mmap_reads: true,
+        ..Default::default()
+    };
+    let db = ShorterDB::new_with_options(&dir, options.clone()).unwrap();
+
+    // Enough flushes to compact, so merged tables are mapped too.
+    for i in 0..3000 {
+        db.set(
+            format!("key{:05}", i % 1000).as_bytes(),
+            format!("value{}", i).as_bytes(),
+        )
+        .unwrap();
+    }
+    db.delete(b"key00010").unwrap();
+    drop(db);
+
+    let db = ShorterDB::new_with_options(&dir, options).unwrap();
+    for i in 2000..3000 {
+        let key = format!("key{:05}", i % 1000);
+        let expected = (key != "key00010").then(|| format!("value{}", i).into_bytes());
+        assert_eq!(db.get(key.as_bytes()).unwrap(), expected);
+    }
+    assert!(db.get(b"missing").is_err());
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use common::fresh_dir;
+use shorterdb::errors::ShortDBErrors;
+use shorterdb::{Options, ShorterDB, StallReason};
+use std::time::Duration;
+
+fn level0_triggers() -> Options {
+    Options {
+        level0_slowdown_writes_trigger: 4,
+        level0_stop_writes_trigger: 5,
+        write_stall_timeout: Duration::from_millis(100),
+        ..Options::default()
+    }
+}
+
+#[test]
+fn test_writes_are_not_held_back_by_default() {
+    let dir = fresh_dir("shorterdb_stall_none");
+    let db = ShorterDB::new(&dir).unwrap();
+    for i in 0..512 {
+        db.set(format!("key{:05}", i).as_bytes(), b"value").unwrap();
+    }
+    db.wait_for_background_work().unwrap();
+    assert_eq!(db.stall_stats(), Default::default());
+}
+
+#[test]
+fn test_level0_files_stall_writes_until_compacted() {
+    let dir = fresh_dir("shorterdb_stall_level0");
+    let db = ShorterDB::new_with_options(&dir, level0_triggers()).unwrap();
+    db.pause_compactions();
+    // Each round fills a Memtable, flushed into one more level 0 table.
+    for round in 0..5 {
+        for i in 0..256 {
+            db.set(format!("key{}-{:05}", round, i).as_bytes(), b"value")
+                .unwrap();
+        }
+        db.wait_for_background_work().unwrap();
+    }
+
+    assert!(matches!(
+        db.set(b"stalled", b"value"),
+        Err(ShortDBErrors::WriteStall(StallReason::Level0Files))
+    ));
+    assert!(matches!(
+        db.get(b"stalled"),
+        Err(ShortDBErrors::KeyNotFound)
+    ));
+    let stats = db.stall_stats();
+    assert!(stats.delayed_writes > 0);
+    assert_eq!(stats.stopped_writes, 1);
+    assert_eq!(stats.timed_out_writes, 1);
+    assert!(stats.level0_files_stall >= Duration::from_millis(100));
+    assert_eq!(stats.immutable_memtables_stall, Duration::ZERO);
+    assert_eq!(stats.last_stall.unwrap().0, StallReason::Level0Files);
+
+    db.resume_compactions();
+    db.wait_for_background_work().unwrap();
+    db.set(b"stalled", b"value").unwrap();
+    assert_eq!(db.get(b"stalled").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(db.stall_stats().timed_out_writes, 1);
+}
+
+#[test]
+fn test_full_memtables_stall_writes_until_flushed() {
+    let dir = fresh_dir("shorterdb_stall_memtables");
+    let options = Options {
+        max_immutable_memtables: 1,
+        ..Options::default()
+    };
+    let db = ShorterDB::new_with_options(&dir, options).unwrap();
+    // Right after a Memtable fills, the next write waits for its flush.
+    for i in 0..6 * 256 {
+        db.set(format!("key{:05}", i).as_bytes(), b"value").unwrap();
+    }
+    db.wait_for_background_work().unwrap();
+
+    let stats = db.stall_stats();
+    assert!(stats.stopped_writes > 0);
+    assert_eq!(stats.timed_out_writes, 0);
+    assert!(stats.immutable_memtables_stall > Duration::ZERO);
+    assert_eq!(stats.level0_files_stall, Duration::ZERO);
+    assert_eq!(stats.last_stall.unwrap().0, StallReason::ImmutableMemtables);
+    assert_eq!(db.iter().count(), 6 * 256);
+}
+
+#[test]
+fn test_contradicting_stall_options_are_rejected() {
+    let dir = fresh_dir("shorterdb_stall_invalid");
+    for options in [
+        Options {
+            max_immutable_memtables: 0,
+            ..Options::default()
+        },
+        Options {
+            level0_slowdown_writes_trigger: 3,
+            ..Options::default()
+        },
+        Options {
+            level0_slowdown_writes_trigger: 8,
+            level0_stop_writes_trigger: 6,
+            ..Options::default()
+        },
+    ] {
+        assert!(matches!(
+            ShorterDB::new_with_options(&dir, options),
+            Err(ShortDBErrors::InvalidOptions(_))
+        ));
+    }
+    assert!(ShorterDB::new_with_options(&dir, level0_triggers()).is_ok());
+}
@@ -0,0 +1,189 @@
+mod common;
+
+use common::fresh_dir;
+use shorterdb::errors::ShortDBErrors;
+use shorterdb::ShorterDB;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn read_u64(value: Option<Vec<u8>>) -> u64 {
+    String::from_utf8(value.unwrap()).unwrap().parse().unwrap()
+}
+
+#[test]
+fn test_optimistic_commit_applies_writes() {
+    let dir = fresh_dir("shorterdb_txn_optimistic_commit");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"from", b"100").unwrap();
+    db.set(b"to", b"0").unwrap();
+
+    let mut txn = db.begin_optimistic();
+    let from = read_u64(txn.get(&db, b"from").unwrap());
+    let to = read_u64(txn.get(&db, b"to").unwrap());
+    txn.set(b"from", (from - 40).to_string().as_bytes());
+    txn.set(b"to", (to + 40).to_string().as_bytes());
+    txn.delete(b"pending");
+    // The transaction sees its own writes, nobody else does yet.
+    assert_eq!(txn.get(&db, b"from").unwrap(), Some(b"60".to_vec()));
+    assert_eq!(db.get(b"from").unwrap(), Some(b"100".to_vec()));
+    txn.commit(&db).unwrap();
+
+    assert_eq!(db.get(b"from").unwrap(), Some(b"60".to_vec()));
+    assert_eq!(db.get(b"to").unwrap(), Some(b"40".to_vec()));
+    assert_eq!(db.get(b"pending").unwrap(), None);
+}
+
+#[test]
+fn test_optimistic_conflict_on_read_key() {
+    let dir = fresh_dir("shorterdb_txn_optimistic_read_conflict");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"counter", b"1").unwrap();
+
+    let mut txn = db.begin_optimistic();
+    let counter = read_u64(txn.get(&db, b"counter").unwrap());
+    // Someone else bumps the counter before we commit.
+    db.set(b"counter", b"5").unwrap();
+    txn.set(b"counter", (counter + 1).to_string().as_bytes());
+    assert!(matches!(txn.commit(&db), Err(ShortDBErrors::Conflict)));
+    assert_eq!(db.get(b"counter").unwrap(), Some(b"5".to_vec()));
+
+    // A key that did not exist yet when it was read counts too.
+    let mut txn = db.begin_optimistic();
+    assert!(txn.get(&db, b"fresh").is_err());
+    db.set(b"fresh", b"theirs").unwrap();
+    txn.set(b"other", b"mine");
+    assert!(matches!(txn.commit(&db), Err(ShortDBErrors::Conflict)));
+    assert!(db.get(b"other").is_err());
+}
+
+#[test]
+fn test_optimistic_conflict_on_written_key() {
+    let dir = fresh_dir("shorterdb_txn_optimistic_write_conflict");
+    let db = ShorterDB::new(&dir).unwrap();
+
+    let mut first = db.begin_optimistic();
+    let mut second = db.begin_optimistic();
+    first.set(b"owner", b"first");
+    second.set(b"owner", b"second");
+    first.commit(&db).unwrap();
+    assert!(matches!(second.commit(&db), Err(ShortDBErrors::Conflict)));
+    assert_eq!(db.get(b"owner").unwrap(), Some(b"first".to_vec()));
+
+    // Unrelated keys do not conflict, even across a flush.
+    let mut txn = db.begin_optimistic();
+    txn.get(&db, b"owner").unwrap();
+    for i in 0..600 {
+        db.set(format!("filler{}", i).as_bytes(), b"x").unwrap();
+    }
+    txn.set(b"owner", b"third");
+    txn.commit(&db).unwrap();
+    assert_eq!(db.get(b"owner").unwrap(), Some(b"third".to_vec()));
+}
+
+#[test]
+fn test_pessimistic_get_for_update_and_commit() {
+    let dir = fresh_dir("shorterdb_txn_pessimistic_commit");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"counter", b"41").unwrap();
+
+    let mut txn = db.begin_pessimistic();
+    let counter = read_u64(txn.get_for_update(&db, b"counter").unwrap());
+    txn.set(b"counter", (counter + 1).to_string().as_bytes())
+        .unwrap();
+    assert_eq!(txn.get(&db, b"counter").unwrap(), Some(b"42".to_vec()));
+
+    // The lock is held until commit.
+    let mut other = db.begin_pessimistic();
+    other.set_lock_timeout(Duration::from_millis(20));
+    assert!(matches!(
+        other.get_for_update(&db, b"counter"),
+        Err(ShortDBErrors::LockTimeout)
+    ));
+    txn.commit(&db).unwrap();
+    assert_eq!(
+        other.get_for_update(&db, b"counter").unwrap(),
+        Some(b"42".to_vec())
+    );
+    other.rollback();
+    assert_eq!(db.get(b"counter").unwrap(), Some(b"42".to_vec()));
+}
+
+#[test]
+fn test_pessimistic_waiter_gets_lock_on_release() {
+    let dir = fresh_dir("shorterdb_txn_pessimistic_wait");
+    let db = ShorterDB::new(&dir).unwrap();
+
+    let mut holder = db.begin_pessimistic();
+    holder.set(b"key", b"holder").unwrap();
+    let mut waiter = db.begin_pessimistic();
+    waiter.set_lock_timeout(Duration::from_secs(10));
+    let handle = thread::spawn(move || {
+        let started = Instant::now();
+        waiter.set(b"key", b"waiter").unwrap();
+        started.elapsed()
+    });
+    thread::sleep(Duration::from_millis(100));
+    holder.rollback();
+    assert!(handle.join().unwrap() >= Duration::from_millis(50));
+}
+
+#[test]
+fn test_pessimistic_deadlock_is_detected() {
+    let dir = fresh_dir("shorterdb_txn_pessimistic_deadlock");
+    let db = ShorterDB::new(&dir).unwrap();
+
+    let mut first = db.begin_pessimistic();
+    let mut second = db.begin_pessimistic();
+    first.set(b"a", b"first").unwrap();
+    second.set(b"b", b"second").unwrap();
+    first.set_lock_timeout(Duration::from_secs(10));
+    second.set_lock_timeout(Duration::from_secs(10));
+
+    // `first` waits for `b`, so `second` waiting for `a` would close the cycle.
+    let handle = thread::spawn(move || {
+        first.set(b"b", b"first").unwrap();
+        first
+    });
+    thread::sleep(Duration::from_millis(100));
+    let started = Instant::now();
+    assert!(matches!(
+        second.set(b"a", b"second"),
+        Err(ShortDBErrors::Deadlock)
+    ));
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // Giving up lets the other transaction through.
+    second.rollback();
+    handle.join().unwrap().commit(&db).unwrap();
+    assert_eq!(db.get(b"a").unwrap(), Some(b"first".to_vec()));
+    assert_eq!(db.get(b"b").unwrap(), Some(b"first".to_vec()));
+}
+
+#[test]
+fn test_pessimistic_savepoints() {
+    let dir = fresh_dir("shorterdb_txn_pessimistic_savepoint");
+    let db = ShorterDB::new(&dir).unwrap();
+
+    let mut txn = db.begin_pessimistic();
+    txn.set(b"a", b"1").unwrap();
+    txn.set_savepoint();
+    txn.set(b"a", b"2").unwrap();
+    txn.set(b"b", b"2").unwrap();
+    txn.set_savepoint();
+    txn.delete(b"a").unwrap();
+    assert_eq!(txn.get(&db, b"a").unwrap(), None);
+
+    txn.rollback_to_savepoint().unwrap();
+    assert_eq!(txn.get(&db, b"a").unwrap(), Some(b"2".to_vec()));
+    txn.rollback_to_savepoint().unwrap();
+    assert_eq!(txn.get(&db, b"a").unwrap(), Some(b"1".to_vec()));
+    assert!(txn.get(&db, b"b").is_err());
+    assert!(matches!(
+        txn.rollback_to_savepoint(),
+        Err(ShortDBErrors::NoSavepoint)
+    ));
+
+    txn.commit(&db).unwrap();
+    assert_eq!(db.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert!(db.get(b"b").is_err());
+}
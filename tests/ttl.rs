@@ -0,0 +1,146 @@
+mod common;
+
+use common::fresh_dir;
+use shorterdb::errors::ShortDBErrors;
+use shorterdb::ShorterDB;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// True if any file under `dir` contains `needle`.
+fn files_contain(dir: &Path, needle: &[u8]) -> bool {
+    fs::read_dir(dir).unwrap().any(|child| {
+        let path = child.unwrap().path();
+        if path.is_dir() {
+            files_contain(&path, needle)
+        } else {
+            let data = fs::read(&path).unwrap();
+            data.windows(needle.len()).any(|w| w == needle)
+        }
+    })
+}
+
+#[test]
+fn test_ttl_keys_read_as_absent_once_expired() {
+    let dir = fresh_dir("shorterdb_ttl_expiry");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"session:a", b"forever").unwrap();
+    db.set_with_ttl(b"session:b", b"short", Duration::from_millis(50))
+        .unwrap();
+    db.set_with_ttl(b"session:c", b"long", Duration::from_secs(3600))
+        .unwrap();
+    assert_eq!(db.get(b"session:b").unwrap(), Some(b"short".to_vec()));
+    assert_eq!(db.iter().count(), 3);
+
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(db.get(b"session:b").unwrap(), None);
+    let keys: Vec<Vec<u8>> = db.iter().map(|r| r.unwrap().0).collect();
+    assert_eq!(keys, vec![b"session:a".to_vec(), b"session:c".to_vec()]);
+    let reversed: Vec<Vec<u8>> = db.iter().rev().map(|r| r.unwrap().0).collect();
+    assert_eq!(reversed, vec![b"session:c".to_vec(), b"session:a".to_vec()]);
+
+    // A plain set replaces the expiring version.
+    db.set(b"session:b", b"renewed").unwrap();
+    assert_eq!(db.get(b"session:b").unwrap(), Some(b"renewed".to_vec()));
+}
+
+#[test]
+fn test_expire_at() {
+    let dir = fresh_dir("shorterdb_ttl_expire_at");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"a", b"1").unwrap();
+    db.set(b"b", b"2").unwrap();
+
+    db.expire_at(b"a", SystemTime::now() - Duration::from_secs(1))
+        .unwrap();
+    db.expire_at(b"b", SystemTime::now() + Duration::from_secs(3600))
+        .unwrap();
+    assert_eq!(db.get(b"a").unwrap(), None);
+    assert_eq!(db.get(b"b").unwrap(), Some(b"2".to_vec()));
+
+    assert!(matches!(
+        db.expire_at(b"a", SystemTime::now()),
+        Err(ShortDBErrors::KeyNotFound)
+    ));
+    assert!(matches!(
+        db.expire_at(b"missing", SystemTime::now()),
+        Err(ShortDBErrors::KeyNotFound)
+    ));
+}
+
+#[test]
+fn test_expiring_at_or_before_the_epoch_expires_at_once() {
+    let dir = fresh_dir("shorterdb_ttl_epoch");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"epoch", b"1").unwrap();
+    db.set(b"before", b"2").unwrap();
+
+    db.expire_at(b"epoch", UNIX_EPOCH).unwrap();
+    db.expire_at(b"before", UNIX_EPOCH - Duration::from_secs(60))
+        .unwrap();
+    assert_eq!(db.get(b"epoch").unwrap(), None);
+    assert_eq!(db.get(b"before").unwrap(), None);
+
+    // Expiry time 0 on disk means "never"; these must not come back.
+    for i in 0..256 {
+        db.set(format!("fill{:03}", i).as_bytes(), b"x").unwrap();
+    }
+    db.wait_for_background_work().unwrap();
+    assert_eq!(db.get(b"epoch").unwrap(), None);
+    assert_eq!(db.get(b"before").unwrap(), None);
+    assert_eq!(db.iter().count(), 256);
+}
+
+#[test]
+fn test_ttl_beyond_the_end_of_time_never_expires() {
+    let dir = fresh_dir("shorterdb_ttl_max");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set_with_ttl(b"k", b"v", Duration::MAX).unwrap();
+    assert_eq!(db.get(b"k").unwrap(), Some(b"v".to_vec()));
+}
+
+#[test]
+fn test_ttl_survives_reopen_and_flush() {
+    let dir = fresh_dir("shorterdb_ttl_reopen");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set_with_ttl(b"flushed", b"x", Duration::from_millis(300))
+        .unwrap();
+    for i in 0..300 {
+        db.set(format!("filler{}", i).as_bytes(), b"x").unwrap();
+    }
+    db.set_with_ttl(b"logged", b"y", Duration::from_millis(300))
+        .unwrap();
+    drop(db);
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.get(b"flushed").unwrap(), Some(b"x".to_vec()));
+    assert_eq!(db.get(b"logged").unwrap(), Some(b"y".to_vec()));
+    thread::sleep(Duration::from_millis(400));
+    assert_eq!(db.get(b"flushed").unwrap(), None);
+    assert_eq!(db.get(b"logged").unwrap(), None);
+}
+
+#[test]
+fn test_compaction_purges_expired_keys() {
+    let dir = fresh_dir("shorterdb_ttl_purge");
+    let db = ShorterDB::new(&dir).unwrap();
+    for i in 0..10 {
+        db.set_with_ttl(
+            format!("session:{}", i).as_bytes(),
+            b"EXPIRED-SESSION-PAYLOAD",
+            Duration::from_millis(50),
+        )
+        .unwrap();
+    }
+    thread::sleep(Duration::from_millis(100));
+
+    // Enough writes to flush and compact level 0 into level 1.
+    for i in 0..1500 {
+        db.set(format!("filler{:05}", i).as_bytes(), b"x").unwrap();
+    }
+    db.wait_for_background_work().unwrap();
+    assert!(dir.join("l1").read_dir().unwrap().next().is_some());
+    assert!(!files_contain(&dir, b"EXPIRED-SESSION-PAYLOAD"));
+    assert!(db.get(b"session:0").is_err());
+}
@@ -0,0 +1,205 @@
+mod common;
+
+use common::fresh_dir;
+use shorterdb::errors::ShortDBErrors;
+use shorterdb::ShorterDB;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+#[test]
+fn test_unflushed_writes_survive_reopen() {
+    let dir = fresh_dir("shorterdb_wal_replay");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"key1", b"value1").unwrap();
+    db.set(b"key2", b"value2").unwrap();
+    db.delete(b"key2").unwrap();
+    drop(db);
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+    assert_eq!(db.get(b"key2").unwrap(), None);
+}
+
+#[test]
+fn test_wal_only_holds_writes_since_the_last_flush() {
+    let dir = fresh_dir("shorterdb_wal_truncate");
+    let db = ShorterDB::new(&dir).unwrap();
+    for i in 0..256 {
+        db.set(format!("key{:03}", i).as_bytes(), b"flushed")
+            .unwrap();
+    }
+    assert_eq!(fs::metadata(dir.join("wal.log")).unwrap().len(), 0);
+
+    db.set(b"key000", b"pending").unwrap();
+    drop(db);
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.get(b"key000").unwrap(), Some(b"pending".to_vec()));
+    assert_eq!(db.get(b"key255").unwrap(), Some(b"flushed".to_vec()));
+
+    // Replaying must not lose anything when the replayed writes are followed
+    // by enough new ones to flush.
+    for i in 0..300 {
+        db.set(format!("more{:03}", i).as_bytes(), b"x").unwrap();
+    }
+    drop(db);
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.get(b"key000").unwrap(), Some(b"pending".to_vec()));
+    assert_eq!(db.get(b"more299").unwrap(), Some(b"x".to_vec()));
+}
+
+#[test]
+fn test_torn_wal_tail_is_dropped() {
+    let dir = fresh_dir("shorterdb_wal_torn");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"key1", b"value1").unwrap();
+    db.set(b"key2", b"value2").unwrap();
+    drop(db);
+
+    // Half of a record header, as left by a crash in the middle of an append.
+    let wal = dir.join("wal.log");
+    let len = fs::metadata(&wal).unwrap().len();
+    let mut file = OpenOptions::new().append(true).open(&wal).unwrap();
+    file.write_all(&[0xde, 0xad, 0xbe, 0xef, 0x20]).unwrap();
+    drop(file);
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(fs::metadata(&wal).unwrap().len(), len);
+    assert_eq!(db.get(b"key2").unwrap(), Some(b"value2".to_vec()));
+    db.set(b"key3", b"value3").unwrap();
+    drop(db);
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.get(b"key3").unwrap(), Some(b"value3".to_vec()));
+}
+
+#[test]
+fn test_corruption_in_the_middle_of_the_wal_is_reported() {
+    let dir = fresh_dir("shorterdb_wal_corrupt");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"key1", b"value1").unwrap();
+    db.set(b"key2", b"value2").unwrap();
+    drop(db);
+
+    // Flip the last byte of the first record's value.
+    let wal = dir.join("wal.log");
+    let mut data = fs::read(&wal).unwrap();
+    let first_record_len = 9 + 4 + b"key1".len() + b"value1".len();
+    data[first_record_len - 1] ^= 0xff;
+    fs::write(&wal, &data).unwrap();
+
+    assert!(matches!(
+        ShorterDB::new(&dir),
+        Err(ShortDBErrors::CorruptedWAL { offset: 0, .. })
+    ));
+}
+
+fn sealed_segments(dir: &Path) -> usize {
+    fs::read_dir(dir)
+        .unwrap()
+        .filter(|e| {
+            let name = e.as_ref().unwrap().file_name();
+            name.to_str().unwrap().starts_with("wal-")
+        })
+        .count()
+}
+
+#[test]
+fn test_sealed_segments_go_once_flushed() {
+    let dir = fresh_dir("shorterdb_wal_segments");
+    let db = ShorterDB::new(&dir).unwrap();
+    for i in 0..1000 {
+        db.set(format!("key{:04}", i).as_bytes(), b"x").unwrap();
+    }
+    db.wait_for_background_work().unwrap();
+    assert_eq!(sealed_segments(&dir), 0);
+    assert!(fs::metadata(dir.join("wal.log")).unwrap().len() > 0);
+}
+
+#[test]
+fn test_sealed_segments_are_replayed_in_order() {
+    let dir = fresh_dir("shorterdb_wal_sealed_replay");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"key", b"old").unwrap();
+    db.set(b"other", b"kept").unwrap();
+    drop(db);
+    // What a crash leaves behind before the flush of a sealed segment.
+    fs::rename(dir.join("wal.log"), dir.join("wal-000001.log")).unwrap();
+
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"key", b"new").unwrap();
+    drop(db);
+    fs::rename(dir.join("wal.log"), dir.join("wal-000002.log")).unwrap();
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.get(b"key").unwrap(), Some(b"new".to_vec()));
+    assert_eq!(db.get(b"other").unwrap(), Some(b"kept".to_vec()));
+    // Replay folds them back into `wal.log`.
+    assert_eq!(sealed_segments(&dir), 0);
+    drop(db);
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.get(b"key").unwrap(), Some(b"new".to_vec()));
+}
+
+#[test]
+fn test_zero_filled_tail_is_dropped() {
+    let dir = fresh_dir("shorterdb_wal_zero_tail");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"key1", b"value1").unwrap();
+    drop(db);
+
+    // A crash after the file grew but before the appended bytes landed.
+    let wal = dir.join("wal.log");
+    let len = fs::metadata(&wal).unwrap().len();
+    let mut file = OpenOptions::new().append(true).open(&wal).unwrap();
+    file.write_all(&[0; 64]).unwrap();
+    drop(file);
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(fs::metadata(&wal).unwrap().len(), len);
+    assert_eq!(db.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+}
+
+#[test]
+fn test_corrupted_length_in_the_middle_of_the_wal_is_reported() {
+    let dir = fresh_dir("shorterdb_wal_corrupt_length");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"key1", b"value1").unwrap();
+    db.set(b"key2", b"value2").unwrap();
+    drop(db);
+
+    // Make the first record claim to run past the end of the file.
+    let wal = dir.join("wal.log");
+    let mut data = fs::read(&wal).unwrap();
+    data[4..8].copy_from_slice(&1000u32.to_le_bytes());
+    fs::write(&wal, &data).unwrap();
+
+    assert!(matches!(
+        ShorterDB::new(&dir),
+        Err(ShortDBErrors::CorruptedWAL { offset: 0, .. })
+    ));
+    assert_eq!(fs::read(&wal).unwrap(), data);
+}
+
+#[test]
+fn test_sealed_segments_are_never_torn() {
+    let dir = fresh_dir("shorterdb_wal_sealed_torn");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"key1", b"value1").unwrap();
+    drop(db);
+
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(dir.join("wal.log"))
+        .unwrap();
+    file.write_all(&[0xde, 0xad, 0xbe, 0xef, 0x20]).unwrap();
+    drop(file);
+    fs::rename(dir.join("wal.log"), dir.join("wal-000001.log")).unwrap();
+
+    assert!(matches!(
+        ShorterDB::new(&dir),
+        Err(ShortDBErrors::CorruptedWAL { .. })
+    ));
+}
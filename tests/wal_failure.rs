@@ -0,0 +1,61 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::fresh_dir;
+use shorterdb::errors::ShortDBErrors;
+use shorterdb::ShorterDB;
+use std::fs;
+
+/// Caps the size of every file this process writes, so appends past `limit`
+/// fail partway with `EFBIG`. Returns the previous cap.
+fn limit_file_size(limit: libc::rlim_t) -> libc::rlimit {
+    unsafe {
+        libc::signal(libc::SIGXFSZ, libc::SIG_IGN);
+        let mut old = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        assert_eq!(libc::getrlimit(libc::RLIMIT_FSIZE, &mut old), 0);
+        let new = libc::rlimit {
+            rlim_cur: limit,
+            rlim_max: old.rlim_max,
+        };
+        assert_eq!(libc::setrlimit(libc::RLIMIT_FSIZE, &new), 0);
+        old
+    }
+}
+
+fn restore_file_size(old: libc::rlimit) {
+    unsafe {
+        assert_eq!(libc::setrlimit(libc::RLIMIT_FSIZE, &old), 0);
+    }
+}
+
+#[test]
+fn test_failed_wal_append_is_cut_off() {
+    let dir = fresh_dir("shorterdb_wal_failure");
+    let db = ShorterDB::new(&dir).unwrap();
+    db.set(b"before", b"1").unwrap();
+    let wal = dir.join("wal.log");
+    let len = fs::metadata(&wal).unwrap().len();
+
+    // Room for part of the record, but not all of it.
+    let old = limit_file_size(len + 16);
+    let failed = db.set(b"failed", &[b'x'; 256]);
+    restore_file_size(old);
+    match failed {
+        Err(ShortDBErrors::Io(e)) => assert_eq!(e.raw_os_error(), Some(libc::EFBIG), "{}", e),
+        other => panic!("expected an io error, got {:?}", other),
+    }
+    assert_eq!(fs::metadata(&wal).unwrap().len(), len);
+    assert!(matches!(db.get(b"failed"), Err(ShortDBErrors::KeyNotFound)));
+
+    db.set(b"after", b"2").unwrap();
+    drop(db);
+
+    let db = ShorterDB::new(&dir).unwrap();
+    assert_eq!(db.get(b"before").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(db.get(b"after").unwrap(), Some(b"2".to_vec()));
+    assert!(matches!(db.get(b"failed"), Err(ShortDBErrors::KeyNotFound)));
+}